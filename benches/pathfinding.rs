@@ -0,0 +1,41 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use robot_tests::{
+    a_star::AStar,
+    ascii_map::parse_ascii_map,
+    hpa_star::HierarchicalGraph,
+    jps::JumpPointSearch,
+    pathfinding::Pathfinder,
+};
+
+const FIXTURES: [(&str, &str); 4] = [
+    ("open", include_str!("../fixtures/maps/open.txt")),
+    ("maze", include_str!("../fixtures/maps/maze.txt")),
+    ("cluttered", include_str!("../fixtures/maps/cluttered.txt")),
+    ("corridor", include_str!("../fixtures/maps/corridor.txt")),
+];
+
+
+fn pathfinding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pathfinding");
+    group.sample_size(10);
+
+    for (name, text) in FIXTURES {
+        let map = parse_ascii_map(text).unwrap();
+        let (start, end, grid) = (map.start.unwrap(), map.end.unwrap(), &map.grid);
+
+        group.bench_with_input(BenchmarkId::new("a_star", name), grid, |b, grid| {
+            b.iter(|| AStar.find_path(start, end, grid))
+        });
+        group.bench_with_input(BenchmarkId::new("jump_point", name), grid, |b, grid| {
+            b.iter(|| JumpPointSearch.find_path(start, end, grid))
+        });
+        // a fresh graph every time, so cluster building is part of the cost
+        group.bench_with_input(BenchmarkId::new("hierarchical", name), grid, |b, grid| {
+            b.iter(|| HierarchicalGraph::new(10).find_path(start, end, grid))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pathfinding);
+criterion_main!(benches);
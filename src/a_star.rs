@@ -1,105 +1,78 @@
-use bevy::prelude::*;
-use crate::grid::{Grid, TileState};
+use bevy::{prelude::*, utils::HashMap};
+use std::{cmp::Reverse, collections::BinaryHeap};
+use crate::{grid::Grid, pathfinding::{Pathfinder, SearchTrace}};
 
-const MAX_SEARCH_DEPTH: usize = 1000;
-const WALKABLE_TILE_STATES: [TileState; 3] = [TileState::Empty, TileState::InteractionPoint, TileState::Robot];
+/// the grid is unbounded, so searches for unreachable goals have to give up at some point
+const MAX_SEARCH_DEPTH: usize = 250_000;
 
 
+pub struct AStar;
 
-/// very basic A* function
-pub fn a_star(start: IVec2, end: IVec2, grid: &Grid, _: usize) -> Option<Vec<IVec2>> {
-    let mut open_list = vec![(start.distance_squared(end), start)];
-    let mut closed_list = Vec::new();
+impl Pathfinder for AStar {
+    fn find_path_traced(&mut self, start: IVec2, goals: &[IVec2], grid: &Grid, trace: Option<&mut SearchTrace>) -> Option<Vec<IVec2>> {
+        a_star(start, goals, grid, trace)
+    }
+}
 
-    for _ in 0..MAX_SEARCH_DEPTH {
-        // println!("\n{:?}", open_list);
-        // println!("{:?}", closed_list);
-        if open_list.is_empty() {return None;}
 
-        let (_, current_pos) = open_list.remove(0); // grab smallest f on open list
-        closed_list.push(current_pos);
+/// A* over the neighbours of each tile, square or hex, to whichever of the goals is nearest
+pub fn a_star(start: IVec2, goals: &[IVec2], grid: &Grid, mut trace: Option<&mut SearchTrace>) -> Option<Vec<IVec2>> {
+    let goals: Vec<IVec2> = goals.iter().copied().filter(|goal| grid.is_walkable(*goal)).collect();
+    if goals.is_empty() {return None;}
+
+    let mut open_list = BinaryHeap::new();
+    let mut g_scores: HashMap<IVec2, u32> = HashMap::new();
+    let mut came_from: HashMap<IVec2, IVec2> = HashMap::new();
+    g_scores.insert(start, 0);
+    // ties on f go to the deeper node, which saves a lot of expansions in open areas
+    let heuristic = |cell: IVec2| goals.iter().map(|goal| grid.layout.distance(cell, *goal)).min().unwrap_or(0);
+    open_list.push(Reverse((heuristic(start), Reverse(0), start.to_array())));
+
+    let mut result = None;
+    for _ in 0..MAX_SEARCH_DEPTH {
+        let Some(Reverse((_, Reverse(g), current_pos))) = open_list.pop() else {break};
+        let current_pos = IVec2::from_array(current_pos);
 
         // found goal
-        if current_pos == end {
-            return Some(trim_a_star(closed_list));
+        if goals.contains(&current_pos) {
+            result = Some(trace_path(&came_from, start, current_pos));
+            break;
         }
+        if g > g_scores[&current_pos] {continue;}
+        if let Some(trace) = trace.as_mut() {trace.explored.push(current_pos);}
 
         // add children
-        let children = get_available_children(current_pos, grid);
-        for child_location in children {
-            if closed_list.contains(&child_location) {continue;}
-            let child_point = (heuristic(start, end, child_location), child_location);
-            if !open_list.contains(&child_point) {open_list.push(child_point)}
-            // println!("{:?}", open_list[open_list.len() - 1]);
+        for child_location in get_available_children(current_pos, grid) {
+            let child_g = g + 1;
+            if child_g < *g_scores.get(&child_location).unwrap_or(&u32::MAX) {
+                g_scores.insert(child_location, child_g);
+                came_from.insert(child_location, current_pos);
+                let f = child_g + heuristic(child_location);
+                open_list.push(Reverse((f, Reverse(child_g), child_location.to_array())));
+            }
         }
-
-        open_list = quicksort(open_list);
-
-        
     }
-    // println!("\n{:?}", open_list);
-    // println!("{:?}", closed_list);
-    return None;
-}
-
-/// sorts the given values list by their accosiated f32 in ascending order
-fn quicksort<T, P: PartialOrd + Copy>(list: Vec<(P, T)>) -> Vec<(P, T)>{
-    if list.len() <= 1 {return list;}
-
-    let mut less = Vec::new();
-    let mut equal = Vec::new();
-    let mut more = Vec::new();
-
-    let target_val = list[list.len() / 2].0;
 
-    for item in list {
-        if item.0 < target_val {less.push(item)}
-        else if item.0 > target_val {more.push(item)}
-        else {equal.push(item)}
+    if let Some(trace) = trace {
+        trace.frontier = open_list.into_iter().map(|Reverse((_, _, cell))| IVec2::from_array(cell)).collect();
     }
-
-    let mut sorted = quicksort(less);
-    sorted.append(&mut equal);
-    sorted.append(&mut quicksort(more));
-    sorted
-}
-
-fn get_available_children(current: IVec2, grid: &Grid) -> Vec<IVec2> {
-    let mut result = Vec::new();
-
-    if WALKABLE_TILE_STATES.contains(&grid[current + IVec2::X]) {result.push(current + IVec2::X)};
-    if WALKABLE_TILE_STATES.contains(&grid[current - IVec2::X]) {result.push(current - IVec2::X)};
-    if WALKABLE_TILE_STATES.contains(&grid[current + IVec2::Y]) {result.push(current + IVec2::Y)};
-    if WALKABLE_TILE_STATES.contains(&grid[current - IVec2::Y]) {result.push(current - IVec2::Y)};
-
     result
 }
 
-
-fn heuristic(_: IVec2, end: IVec2, current: IVec2) -> i32 {
-    current.distance_squared(end)
+fn get_available_children(current: IVec2, grid: &Grid) -> Vec<IVec2> {
+    grid.layout.neighbours().iter()
+        .map(|offset| current + *offset)
+        .filter(|cell| grid.is_walkable(*cell))
+        .collect()
 }
 
-
-fn trim_a_star(mut path: Vec<IVec2>) -> Vec<IVec2>{
-    if path.len() <= 2 {
-        return path;
-    }
-
-    let mut i = 0;
-    let mut trimmed_path = vec![path.pop().unwrap()];
-    path.reverse();
-
-    loop {
-        if path.len() <= i {break;}
-
-        if trimmed_path.last().unwrap().distance_squared(path[i]) == 1 {
-            trimmed_path.push(path.remove(i));
-        } else {
-            i += 1;
-        }
+/// follows the parents back from end to start, the path comes out end first
+pub fn trace_path(came_from: &HashMap<IVec2, IVec2>, start: IVec2, end: IVec2) -> Vec<IVec2> {
+    let mut path = vec![end];
+    let mut current = end;
+    while current != start {
+        current = came_from[&current];
+        path.push(current);
     }
-
-
-    trimmed_path
-}
\ No newline at end of file
+    path
+}
@@ -0,0 +1,121 @@
+use bevy::prelude::*;
+
+use crate::grid::{Grid, Object};
+
+
+/// A grid loaded from a text map, along with the endpoints marked in it
+pub struct AsciiMap {
+    pub grid: Grid,
+    pub start: Option<IVec2>,
+    pub end: Option<IVec2>,
+}
+
+/// Reads a text map into a grid with a tile size of one.
+///
+/// `#` is a wall, `B` a building tile, `S` and `E` the start and end of a path and `.` empty ground.
+/// The top line of the text is the highest row of the grid, and the first character of each line is x = 0.
+pub fn parse_ascii_map(text: &str) -> Result<AsciiMap, String> {
+    let mut map = AsciiMap {
+        grid: Grid::new(Vec2::ZERO, 1.0),
+        start: None,
+        end: None,
+    };
+
+    let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+    let height = lines.len() as i32;
+    for (row, line) in lines.iter().enumerate() {
+        for (x, symbol) in line.trim_end().chars().enumerate() {
+            let cell = IVec2::new(x as i32, height - 1 - row as i32);
+            match symbol {
+                '.' => {},
+                '#' => {map.grid.set_object(cell, Some(Object::Wall));},
+                'B' => {map.grid.set_object(cell, Some(Object::Building));},
+                'S' if map.start.is_none() => map.start = Some(cell),
+                'E' if map.end.is_none() => map.end = Some(cell),
+                'S' | 'E' => return Err(format!("second '{}' at line {}", symbol, row + 1)),
+                _ => return Err(format!("unknown symbol '{}' at line {}, column {}", symbol, row + 1, x + 1)),
+            }
+        }
+    }
+
+    Ok(map)
+}
+
+
+#[cfg(test)]
+mod golden_tests {
+    use super::{parse_ascii_map, AsciiMap};
+    use crate::{a_star::AStar, grid::{GridLayout, Object}, hpa_star::HierarchicalGraph, jps::JumpPointSearch, pathfinding::Pathfinder};
+    use bevy::prelude::*;
+
+    /// every fixture with the length of its shortest path in tiles, counting both ends
+    const FIXTURES: [(&str, &str, Option<usize>); 6] = [
+        ("open", include_str!("../fixtures/maps/open.txt"), Some(123)),
+        ("cluttered", include_str!("../fixtures/maps/cluttered.txt"), Some(123)),
+        ("maze_small", include_str!("../fixtures/maps/maze_small.txt"), Some(189)),
+        ("maze", include_str!("../fixtures/maps/maze.txt"), Some(941)),
+        ("corridor", include_str!("../fixtures/maps/corridor.txt"), Some(1141)),
+        ("unreachable", include_str!("../fixtures/maps/unreachable.txt"), None),
+    ];
+
+    fn check(name: &str, map: &AsciiMap, path: Option<Vec<IVec2>>, expected: Option<usize>, optimal: bool) {
+        let (start, end) = (map.start.unwrap(), map.end.unwrap());
+        let Some(path) = path else {
+            assert_eq!(expected, None, "{}: no path found", name);
+            return;
+        };
+        let expected = expected.unwrap_or_else(|| panic!("{}: found a path to an unreachable goal", name));
+
+        assert_eq!(path[0], end, "{}", name);
+        assert_eq!(*path.last().unwrap(), start, "{}", name);
+        for pair in path.windows(2) {
+            assert_eq!(GridLayout::Square.distance(pair[0], pair[1]), 1, "{}: path jumps from {} to {}", name, pair[1], pair[0]);
+            assert!(map.grid.is_walkable(pair[0]), "{}: path goes through {}", name, pair[0]);
+        }
+        if optimal {
+            assert_eq!(path.len(), expected, "{}", name);
+        } else {
+            assert!(path.len() >= expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn parsing() {
+        let map = parse_ascii_map("#.E\nSB.\n").unwrap();
+        assert_eq!(map.start, Some(IVec2::new(0, 0)));
+        assert_eq!(map.end, Some(IVec2::new(2, 1)));
+        assert_eq!(map.grid[IVec2::new(0, 1)].object, Some(Object::Wall));
+        assert_eq!(map.grid[IVec2::new(1, 0)].object, Some(Object::Building));
+        assert!(map.grid.is_free(IVec2::new(1, 1)));
+
+        assert!(parse_ascii_map("S.x").is_err());
+        assert!(parse_ascii_map("S.S").is_err());
+    }
+
+    #[test]
+    fn a_star_golden() {
+        for (name, text, expected) in FIXTURES {
+            let map = parse_ascii_map(text).unwrap();
+            let path = AStar.find_path(map.start.unwrap(), map.end.unwrap(), &map.grid);
+            check(name, &map, path, expected, true);
+        }
+    }
+
+    #[test]
+    fn jump_point_golden() {
+        for (name, text, expected) in FIXTURES {
+            let map = parse_ascii_map(text).unwrap();
+            let path = JumpPointSearch.find_path(map.start.unwrap(), map.end.unwrap(), &map.grid);
+            check(name, &map, path, expected, true);
+        }
+    }
+
+    #[test]
+    fn hierarchical_golden() {
+        for (name, text, expected) in FIXTURES {
+            let map = parse_ascii_map(text).unwrap();
+            let path = HierarchicalGraph::new(10).find_path(map.start.unwrap(), map.end.unwrap(), &map.grid);
+            check(name, &map, path, expected, false);
+        }
+    }
+}
@@ -2,7 +2,7 @@ use bevy::{prelude::*, asset::LoadedFolder, utils::HashMap};
 use ron::from_str;
 use std::fs;
 use serde::Deserialize;
-use crate::{AppState, interaction::InteractionSpriteIndices};
+use crate::{AppState, doors::DOOR_FRAMES, interaction::InteractionSpriteIndices, grid::{Grid, GridLayout}, walls::WallTypes};
 
 const BUILDING_SPRITE_PATH: &str = "robot_game/sprites/buildings";
 const SELECTOR_SPRITE_PATH: &str = "robot_game/sprites/selector_images";
@@ -29,8 +29,12 @@ pub struct LoadedFolderCount(usize);
 #[derive(Resource)]
 pub struct BuildingAtlasHandle(pub Handle<TextureAtlas>);
 
+/// an atlas for each of the `WallTypes`, in the same order
 #[derive(Resource)]
-pub struct WallAtlasHandle(pub Handle<TextureAtlas>);
+pub struct WallAtlasHandles(pub Vec<Handle<TextureAtlas>>);
+
+#[derive(Resource)]
+pub struct DoorAtlasHandle(pub Handle<TextureAtlas>);
 
 #[derive(Resource)]
 pub struct SelectionSpriteAtlasHandle(pub Handle<TextureAtlas>);
@@ -67,9 +71,10 @@ pub fn load_all_folders(
     mut commands: Commands,
     asset_server: ResMut<AssetServer>,
 ) {
-    let mut folders = Vec::new();
-    folders.push(asset_server.load_folder(BUILDING_SPRITE_PATH));
-    folders.push(asset_server.load_folder(SELECTOR_SPRITE_PATH));
+    let folders = vec![
+        asset_server.load_folder(BUILDING_SPRITE_PATH),
+        asset_server.load_folder(SELECTOR_SPRITE_PATH),
+    ];
     commands.insert_resource(AssetFolderHandles {
         handles: folders,
     });
@@ -95,29 +100,41 @@ pub fn check_folders_loaded(
 }
 
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_atlases(
     asset_server: Res<AssetServer>,
     loaded_folders: Res<Assets<LoadedFolder>>,
     folder_handles: Res<AssetFolderHandles>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut textures: ResMut<Assets<Image>>,
+    grid: Res<Grid>,
+    wall_types: Res<WallTypes>,
     mut commands: Commands
 ) {
-    create_wall_atlas(&asset_server, &mut texture_atlases, &mut commands);
+    create_wall_atlases(&asset_server, grid.layout, &wall_types, &mut texture_atlases, &mut commands);
     create_building_atlas(&loaded_folders, &folder_handles, &mut texture_atlases, &mut textures, &mut commands);
     create_info_sprites_atlas(&loaded_folders, &folder_handles, &mut texture_atlases, &mut textures, &mut commands);
     create_robot_atlas(&asset_server, &mut texture_atlases, &mut commands);
+    create_door_atlas(&asset_server, &mut texture_atlases, &mut commands);
 }
 
 
-fn create_wall_atlas(
+fn create_wall_atlases(
     asset_server: &AssetServer,
+    layout: GridLayout,
+    wall_types: &WallTypes,
     texture_atlases: &mut Assets<TextureAtlas>,
     commands: &mut Commands
 ) {
-    let walls_handle = asset_server.load("robot_game/sprite_sheets/walls.png");
-    let walls_atlas = TextureAtlas::from_grid(walls_handle, Vec2::new(50.0, 50.0), 4, 4, None, None);
-    commands.insert_resource(WallAtlasHandle(texture_atlases.add(walls_atlas)));
+    // squares have the 47 blob tiles, hexes a sprite for each of the 64 combinations of neighbours
+    let handles = wall_types.0.iter().map(|info| {
+        let atlas = match layout {
+            GridLayout::Square => TextureAtlas::from_grid(asset_server.load(["robot_game/sprite_sheets", &info.sheet].join("/")), Vec2::new(50.0, 50.0), 8, 6, None, None),
+            GridLayout::Hex => TextureAtlas::from_grid(asset_server.load(["robot_game/sprite_sheets", &info.hex_sheet].join("/")), Vec2::new(50.0, 50.0), 8, 8, None, None),
+        };
+        texture_atlases.add(atlas)
+    }).collect();
+    commands.insert_resource(WallAtlasHandles(handles));
 }
 
 
@@ -190,6 +207,16 @@ fn create_robot_atlas(
     commands.insert_resource(RobotAtlasHandle(texture_atlases.add(robot_atlas)));
 }
 
+/// a row of frames from shut to open for each way a door can face
+fn create_door_atlas(
+    asset_server: &AssetServer,
+    texture_atlases: &mut Assets<TextureAtlas>,
+    commands: &mut Commands
+) {
+    let doors_handle = asset_server.load("robot_game/sprite_sheets/doors.png");
+    let doors_atlas = TextureAtlas::from_grid(doors_handle, Vec2::new(50.0, 50.0), DOOR_FRAMES, 2, None, None);
+    commands.insert_resource(DoorAtlasHandle(texture_atlases.add(doors_atlas)));
+}
 
 
 
@@ -198,11 +225,32 @@ struct BuildingInfo {
     name: String,
     world_sprite: String,
     ui_sprite: String,
-    size: [usize; 2]
+    size: [usize; 2],
+    /// tiles robots stand on to use the building, relative to its bottom left tile
+    #[serde(default)]
+    interaction_points: Vec<[i32; 2]>,
+    /// has to be built over an ore deposit, which it then mines
+    #[serde(default)]
+    requires_node: bool
+}
+
+pub struct BuildingSpawnInfo {
+    pub sprite_index: usize,
+    pub name: String,
+    /// offset from the bottom left tile to the top right one
+    pub max_offset: IVec2,
+    pub interaction_points: Vec<IVec2>,
+    pub requires_node: bool
 }
 
 #[derive(Resource)]
-pub struct BuildingBindings(pub HashMap<usize, (usize, String, IVec2)>);
+pub struct BuildingBindings(pub HashMap<usize, BuildingSpawnInfo>);
+
+impl BuildingBindings {
+    pub fn by_name(&self, name: &str) -> Option<&BuildingSpawnInfo> {
+        self.0.values().find(|info| info.name == name)
+    }
+}
 
 pub fn create_building_selector_bindings(
     asset_server: Res<AssetServer>,
@@ -210,6 +258,7 @@ pub fn create_building_selector_bindings(
     texture_atlases: Res<Assets<TextureAtlas>>,
     building_atlas: Res<BuildingAtlasHandle>,
     select_atlas: Res<SelectionSpriteAtlasHandle>,
+    wall_types: Res<WallTypes>,
 ) {
     let building_bindings_str = fs::read_to_string("assets/building_bindings.ron").unwrap();
 
@@ -230,14 +279,24 @@ pub fn create_building_selector_bindings(
         let ui_sprite_handle = asset_server.get_handle([SELECTOR_SPRITE_PATH, &binding.ui_sprite].join("/")).unwrap();
         let world_sprite_index = building_atlas.get_texture_index(world_sprite_handle).unwrap();
         let ui_sprite_index = ui_atlas.get_texture_index(ui_sprite_handle).unwrap();
-        bindings_map.insert(ui_sprite_index, (world_sprite_index, binding.name, IVec2::new(binding.size[0] as i32 - 1, binding.size[1] as i32 - 1)));
+        bindings_map.insert(ui_sprite_index, BuildingSpawnInfo {
+            sprite_index: world_sprite_index,
+            name: binding.name,
+            max_offset: IVec2::new(binding.size[0] as i32 - 1, binding.size[1] as i32 - 1),
+            interaction_points: binding.interaction_points.into_iter().map(IVec2::from_array).collect(),
+            requires_node: binding.requires_node
+        });
     }
 
     commands.insert_resource(BuildingBindings(bindings_map));
 
     commands.insert_resource(InteractionSpriteIndices {
         delete: ui_atlas.get_texture_index(asset_server.get_handle([SELECTOR_SPRITE_PATH, "Delete.png"].join("/")).unwrap()).unwrap(),
-        wall: ui_atlas.get_texture_index(asset_server.get_handle([SELECTOR_SPRITE_PATH, "Wall.png"].join("/")).unwrap()).unwrap()
+        walls: wall_types.0.iter()
+            .map(|info| ui_atlas.get_texture_index(asset_server.get_handle([SELECTOR_SPRITE_PATH, &info.ui_sprite].join("/")).unwrap()).unwrap())
+            .collect(),
+        door: ui_atlas.get_texture_index(asset_server.get_handle([SELECTOR_SPRITE_PATH, "Door.png"].join("/")).unwrap()).unwrap(),
+        count: ui_atlas.len()
     });
 
 }
\ No newline at end of file
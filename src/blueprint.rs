@@ -0,0 +1,409 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bevy::{prelude::*, utils::HashSet};
+use ron::ser::{to_string_pretty, PrettyConfig};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::{
+    asset_loading::BuildingBindings,
+    building::building_fits,
+    doors::Door,
+    walls::WallTypes,
+    grid::{grid_to_space, Grid},
+    history::{Action, GridEditor, History, Placed, PlacedParts},
+    interaction::TileSelectIndicator,
+    save::{BuildingSave, DoorSave},
+    AppState
+};
+
+const BLUEPRINT_PATH: &str = "blueprints/blueprint.ron";
+/// a share string pasted in here is read by Ctrl+I
+const IMPORT_PATH: &str = "blueprints/import.txt";
+const SHARE_PREFIX: &str = "bp1:";
+
+const SELECTION_COLOUR: Color = Color::rgba(0.3, 0.6, 1.0, 0.25);
+const GHOST_COLOUR: Color = Color::rgba(0.2, 1.0, 0.3, 0.4);
+const BLOCKED_GHOST_COLOUR: Color = Color::rgba(1.0, 0.2, 0.2, 0.4);
+
+
+pub struct BlueprintPlugin;
+
+impl Plugin for BlueprintPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<BlueprintTool>()
+            .add_systems(Update, (select_area, blueprint_keys, paste_blueprint, draw_blueprint_overlay)
+                .chain()
+                .run_if(in_state(AppState::Finished)));
+    }
+}
+
+
+/// Walls, doors and buildings copied out of the world, positioned relative to the bottom left of the copied area
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Blueprint {
+    pub size: [i32; 2],
+    pub walls: Vec<[i32; 2]>,
+    pub buildings: Vec<BuildingSave>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub doors: Vec<[i32; 2]>,
+    /// the type of each wall that isn't of the default type
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wall_types: Vec<([i32; 2], String)>,
+    /// how each door that isn't a plain unlocked one is set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub door_settings: Vec<DoorSave>,
+}
+
+impl Blueprint {
+    /// keeps everything lying entirely inside the area, given each thing with its top right tile
+    pub fn capture(min: IVec2, max: IVec2, default_wall: &str, placed: impl Iterator<Item = (Placed, IVec2)>) -> Self {
+        let mut blueprint = Blueprint {
+            size: (max - min + IVec2::ONE).to_array(),
+            ..Default::default()
+        };
+        for (placed, top_right) in placed {
+            let location = match &placed {Placed::Wall(_, location) | Placed::Building(_, location, _) | Placed::Door(location, _) => *location};
+            if location.cmplt(min).any() || top_right.cmpgt(max).any() {continue;}
+            match placed {
+                Placed::Wall(name, location) => {
+                    blueprint.walls.push((location - min).to_array());
+                    if name != default_wall {blueprint.wall_types.push(((location - min).to_array(), name));}
+                },
+                Placed::Building(name, location, rotation) => blueprint.buildings.push(BuildingSave {
+                    name,
+                    location: (location - min).to_array(),
+                    rotation,
+                    inventory: Vec::new(),
+                    progress: None
+                }),
+                Placed::Door(location, door) => {
+                    blueprint.doors.push((location - min).to_array());
+                    if door != Door::default() {
+                        blueprint.door_settings.push(DoorSave {location: (location - min).to_array(), admits_robots: door.admits_robots, locked: door.locked});
+                    }
+                },
+            }
+        }
+        blueprint.walls.sort();
+        blueprint.doors.sort();
+        blueprint.door_settings.sort_by_key(|door| door.location);
+        blueprint.wall_types.sort();
+        blueprint.buildings.sort_by_key(|building| building.location);
+        blueprint
+    }
+
+    /// what pasting with the bottom left corner at `origin` would place
+    pub fn placements(&self, origin: IVec2, default_wall: &str) -> Vec<Placed> {
+        self.walls.iter().map(|wall| {
+            let name = self.wall_types.iter().find(|(cell, _)| cell == wall).map_or(default_wall, |(_, name)| name);
+            Placed::Wall(name.to_string(), origin + IVec2::from_array(*wall))
+        })
+            .chain(self.buildings.iter().map(|building| {
+                Placed::Building(building.name.clone(), origin + IVec2::from_array(building.location), building.rotation)
+            }))
+            .chain(self.doors.iter().map(|cell| {
+                let door = self.door_settings.iter().find(|door| door.location == *cell)
+                    .map_or(Door::default(), |door| Door {admits_robots: door.admits_robots, locked: door.locked});
+                Placed::Door(origin + IVec2::from_array(*cell), door)
+            }))
+            .collect()
+    }
+
+    /// every tile pasting at `origin` would cover, buildings with no binding take up no room
+    pub fn footprint(&self, origin: IVec2, bindings: &BuildingBindings) -> Vec<IVec2> {
+        let mut cells: Vec<IVec2> = self.walls.iter().chain(self.doors.iter()).map(|cell| origin + IVec2::from_array(*cell)).collect();
+        for building in self.buildings.iter() {
+            let Some(spawn_info) = bindings.by_name(&building.name) else {continue};
+            let location = origin + IVec2::from_array(building.location);
+            let size = building.rotation.rotate_size(spawn_info.max_offset);
+            for x in 0..=size.x {
+                for y in 0..=size.y {
+                    cells.push(location + IVec2::new(x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    pub fn fits(&self, origin: IVec2, grid: &Grid, bindings: &BuildingBindings) -> bool {
+        self.walls.iter().chain(self.doors.iter()).all(|cell| grid.is_free(origin + IVec2::from_array(*cell)))
+            && self.buildings.iter().all(|building| bindings.by_name(&building.name).is_none_or(|spawn_info| {
+                building_fits(grid, origin + IVec2::from_array(building.location), building.rotation, spawn_info)
+            }))
+    }
+
+    /// compact RON, base64 encoded so it survives being pasted into chat
+    pub fn to_share_string(&self) -> String {
+        let ron = ron::to_string(self).expect("blueprints always serialize");
+        format!("{}{}", SHARE_PREFIX, URL_SAFE_NO_PAD.encode(ron))
+    }
+
+    pub fn from_share_string(text: &str) -> Result<Self, String> {
+        let encoded = text.trim().strip_prefix(SHARE_PREFIX).ok_or("not a blueprint string")?;
+        let bytes = URL_SAFE_NO_PAD.decode(encoded).map_err(|e| e.to_string())?;
+        let ron = String::from_utf8(bytes).map_err(|e| e.to_string())?;
+        ron::from_str(&ron).map_err(|e| e.to_string())
+    }
+}
+
+
+/// Copying and pasting areas.
+///
+/// Alt + drag selects an area, Ctrl+C copies it and Ctrl+V shows the copy under the cursor until a left click
+/// places it (or Escape cancels). Ctrl+S / Ctrl+L save and load the copy, Ctrl+E prints it as a share string
+/// and Ctrl+I reads one from `blueprints/import.txt`.
+#[derive(Resource, Default)]
+pub struct BlueprintTool {
+    pub selection_start: Option<IVec2>,
+    pub selection: Option<(IVec2, IVec2)>,
+    pub clipboard: Option<Blueprint>,
+    pub pasting: bool,
+}
+
+impl BlueprintTool {
+    /// whether mouse clicks belong to the blueprint tool rather than normal placement
+    pub fn is_busy(&self, keyboard: &Input<KeyCode>) -> bool {
+        self.pasting || self.selection_start.is_some() || keyboard.any_pressed([KeyCode::AltLeft, KeyCode::AltRight])
+    }
+}
+
+#[derive(Component)]
+pub struct BlueprintOverlayTag;
+
+
+pub fn select_area(
+    keyboard: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    tile_select: Query<&TileSelectIndicator>,
+    mut tool: ResMut<BlueprintTool>
+) {
+    let pos = tile_select.single().pos;
+    if mouse.just_pressed(MouseButton::Left) && !tool.pasting && keyboard.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]) {
+        tool.selection_start = Some(pos);
+    }
+    let Some(start) = tool.selection_start else {return};
+
+    let selection = Some((start.min(pos), start.max(pos)));
+    if tool.selection != selection {tool.selection = selection;}
+    if !mouse.pressed(MouseButton::Left) {tool.selection_start = None;}
+}
+
+pub fn blueprint_keys(
+    keyboard: Res<Input<KeyCode>>,
+    mut tool: ResMut<BlueprintTool>,
+    grid: Res<Grid>,
+    wall_types: Res<WallTypes>,
+    placed_query: Query<PlacedParts>
+) {
+    if keyboard.just_pressed(KeyCode::Escape) && tool.pasting {tool.pasting = false;}
+    if !keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {return;}
+
+    if keyboard.just_pressed(KeyCode::C) {
+        let Some((min, max)) = tool.selection else {return};
+        let mut owners = HashSet::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                owners.extend(grid.owner(IVec2::new(x, y)));
+            }
+        }
+        let placed = owners.into_iter().filter_map(|owner| placed_query.get(owner).ok()).filter_map(|(grid_entity, kind, building, door)| {
+            let placed = match (kind, building, door) {
+                (Some(kind), _, _) => Placed::Wall(wall_types.0[kind.0].name.clone(), grid_entity.min),
+                (None, Some(building), _) => Placed::Building(building.name.clone(), grid_entity.min, building.rotation),
+                (None, None, Some(door)) => Placed::Door(grid_entity.min, *door),
+                (None, None, None) => return None,
+            };
+            Some((placed, grid_entity.max))
+        });
+        let blueprint = Blueprint::capture(min, max, wall_types.default_name(), placed);
+        info!("Copied {} walls, {} doors and {} buildings", blueprint.walls.len(), blueprint.doors.len(), blueprint.buildings.len());
+        tool.clipboard = Some(blueprint);
+        tool.selection = None;
+    } else if keyboard.just_pressed(KeyCode::V) {
+        tool.pasting = tool.clipboard.is_some();
+    } else if keyboard.just_pressed(KeyCode::S) {
+        let Some(blueprint) = tool.clipboard.as_ref() else {return};
+        let result = to_string_pretty(blueprint, PrettyConfig::default()).map_err(|e| e.to_string())
+            .and_then(|text| {
+                fs::create_dir_all("blueprints").map_err(|e| e.to_string())?;
+                fs::write(BLUEPRINT_PATH, text).map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(()) => info!("Saved blueprint to {}", BLUEPRINT_PATH),
+            Err(e) => warn!("Failed to save blueprint: {}", e)
+        }
+    } else if keyboard.just_pressed(KeyCode::L) || keyboard.just_pressed(KeyCode::I) {
+        let result = if keyboard.just_pressed(KeyCode::L) {
+            fs::read_to_string(BLUEPRINT_PATH).map_err(|e| e.to_string())
+                .and_then(|text| ron::from_str::<Blueprint>(&text).map_err(|e| e.to_string()))
+        } else {
+            fs::read_to_string(IMPORT_PATH).map_err(|e| e.to_string())
+                .and_then(|text| Blueprint::from_share_string(&text))
+        };
+        match result {
+            Ok(blueprint) => {
+                tool.clipboard = Some(blueprint);
+                tool.pasting = true;
+            },
+            Err(e) => warn!("Failed to load blueprint: {}", e)
+        }
+    } else if keyboard.just_pressed(KeyCode::E) {
+        let Some(blueprint) = tool.clipboard.as_ref() else {return};
+        info!("Blueprint: {}", blueprint.to_share_string());
+    }
+}
+
+pub fn paste_blueprint(
+    mouse: Res<Input<MouseButton>>,
+    tile_select: Query<&TileSelectIndicator>,
+    mut tool: ResMut<BlueprintTool>,
+    mut history: ResMut<History>,
+    mut editor: GridEditor
+) {
+    if !tool.pasting || !mouse.just_pressed(MouseButton::Left) {return;}
+    let Some(blueprint) = tool.clipboard.as_ref() else {return};
+    let origin = tile_select.single().pos;
+    let (grid, bindings) = (editor.grid(), editor.bindings());
+    if !blueprint.fits(origin, grid, bindings) || !blueprint.footprint(origin, bindings).iter().all(|cell| grid.is_explored(*cell)) {return;}
+
+    // the whole paste is undone in one go
+    history.begin_stroke();
+    for placed in blueprint.placements(origin, editor.wall_types().default_name()) {
+        if editor.place(&placed) {history.record(Action::Place(placed));}
+    }
+    history.end_stroke();
+    tool.pasting = false;
+}
+
+/// draws the selected area and the ghost of a paste, only when one of them has changed
+pub fn draw_blueprint_overlay(
+    mut commands: Commands,
+    tool: Res<BlueprintTool>,
+    tile_select: Query<&TileSelectIndicator>,
+    overlay_query: Query<Entity, With<BlueprintOverlayTag>>,
+    bindings: Res<BuildingBindings>,
+    grid: Res<Grid>,
+    mut last_cursor: Local<IVec2>
+) {
+    let cursor = tile_select.single().pos;
+    let ghost_changed = tool.pasting && (cursor != *last_cursor || grid.is_changed());
+    if !tool.is_changed() && !ghost_changed {return;}
+    *last_cursor = cursor;
+
+    for entity in overlay_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    if let Some((min, max)) = tool.selection {
+        let centre = (grid_to_space(min, &grid) + grid_to_space(max, &grid)) / 2.0;
+        commands.spawn(SpriteBundle {
+            sprite: Sprite {
+                color: SELECTION_COLOUR,
+                custom_size: Some((max - min + IVec2::ONE).as_vec2() * grid.tile_size),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(centre.extend(0.7)),
+            ..Default::default()
+        })
+        .insert(BlueprintOverlayTag);
+    }
+
+    let Some(blueprint) = tool.clipboard.as_ref().filter(|_| tool.pasting) else {return};
+    let footprint = blueprint.footprint(cursor, &bindings);
+    let fits = blueprint.fits(cursor, &grid, &bindings) && footprint.iter().all(|cell| grid.is_explored(*cell));
+    for cell in footprint {
+        let colour = if fits || (grid.is_free(cell) && grid.is_explored(cell)) {GHOST_COLOUR} else {BLOCKED_GHOST_COLOUR};
+        commands.spawn(SpriteBundle {
+            sprite: Sprite {
+                color: colour,
+                custom_size: Some(Vec2::splat(grid.tile_size)),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(grid_to_space(cell, &grid).extend(0.7)),
+            ..Default::default()
+        })
+        .insert(BlueprintOverlayTag);
+    }
+}
+
+
+#[cfg(test)]
+mod blueprint_tests {
+    use super::Blueprint;
+    use crate::{
+        asset_loading::{BuildingBindings, BuildingSpawnInfo},
+        building::Rotation,
+        doors::Door,
+        grid::{Grid, Object},
+        history::Placed,
+        save::DoorSave
+    };
+    use bevy::{prelude::*, utils::HashMap};
+
+    fn bindings() -> BuildingBindings {
+        BuildingBindings(HashMap::from([(0, BuildingSpawnInfo {
+            sprite_index: 0,
+            name: "Crafter".to_string(),
+            max_offset: IVec2::new(2, 1),
+            interaction_points: vec![],
+            requires_node: false
+        })]))
+    }
+
+    fn example() -> Blueprint {
+        let placed = [
+            (Placed::Wall("Stone".to_string(), IVec2::new(10, 10)), IVec2::new(10, 10)),
+            (Placed::Wall("Brick".to_string(), IVec2::new(12, 14)), IVec2::new(12, 14)),
+            (Placed::Door(IVec2::new(14, 12), Door::default()), IVec2::new(14, 12)),
+            (Placed::Door(IVec2::new(10, 13), Door {admits_robots: true, locked: true}), IVec2::new(10, 13)),
+            (Placed::Building("Crafter".to_string(), IVec2::new(11, 11), Rotation::R90), IVec2::new(12, 13)),
+            // sticks out of the area, so it is left behind
+            (Placed::Building("Crafter".to_string(), IVec2::new(13, 10), Rotation::R0), IVec2::new(15, 11)),
+        ];
+        Blueprint::capture(IVec2::new(10, 10), IVec2::new(14, 14), "Stone", placed.into_iter())
+    }
+
+    #[test]
+    fn capture_and_place() {
+        let blueprint = example();
+        assert_eq!(blueprint.size, [5, 5]);
+        assert_eq!(blueprint.walls, vec![[0, 0], [2, 4]]);
+        assert_eq!(blueprint.buildings.len(), 1);
+        assert_eq!(blueprint.doors, vec![[0, 3], [4, 2]]);
+        assert_eq!(blueprint.door_settings, vec![DoorSave {location: [0, 3], admits_robots: true, locked: true}]);
+        assert_eq!(blueprint.wall_types, vec![([2, 4], "Brick".to_string())]);
+
+        assert_eq!(blueprint.placements(IVec2::new(-5, 0), "Stone"), vec![
+            Placed::Wall("Stone".to_string(), IVec2::new(-5, 0)),
+            Placed::Wall("Brick".to_string(), IVec2::new(-3, 4)),
+            Placed::Building("Crafter".to_string(), IVec2::new(-4, 1), Rotation::R90),
+            Placed::Door(IVec2::new(-5, 3), Door {admits_robots: true, locked: true}),
+            Placed::Door(IVec2::new(-1, 2), Door::default()),
+        ]);
+        assert_eq!(blueprint.footprint(IVec2::ZERO, &bindings()).len(), 4 + 6);
+    }
+
+    #[test]
+    fn collisions() {
+        let blueprint = example();
+        let mut grid = Grid::new(Vec2::ZERO, 1.0);
+        grid.set_object(IVec2::new(3, 3), Some(Object::Wall));
+
+        assert!(blueprint.fits(IVec2::new(3, 4), &grid, &bindings()));
+        // lands the rotated crafter on the wall
+        assert!(!blueprint.fits(IVec2::new(1, 0), &grid, &bindings()));
+        assert!(!blueprint.fits(IVec2::new(3, 3), &grid, &bindings()));
+    }
+
+    #[test]
+    fn share_strings() {
+        let blueprint = example();
+        let text = blueprint.to_share_string();
+        assert!(text.starts_with("bp1:"));
+        assert!(!text.contains(char::is_whitespace));
+        assert_eq!(Blueprint::from_share_string(&text), Ok(blueprint));
+        assert!(Blueprint::from_share_string("bp1:!!").is_err());
+        assert!(Blueprint::from_share_string("hello").is_err());
+    }
+}
@@ -1,11 +1,12 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::{grid::{Grid, TileState, grid_to_space, GridEntity, GridScale}, asset_loading::BuildingAtlasHandle};
+use crate::{grid::{Floor, Grid, Object, grid_to_space, GridEntity, GridScale}, asset_loading::{BuildingAtlasHandle, BuildingSpawnInfo}, item::Inventory, mining::Miner};
 
 pub struct BuildingPlugin;
 
 impl Plugin for BuildingPlugin {
-    fn build(&self, app: &mut App) {
+    fn build(&self, _app: &mut App) {
 
     }
 }
@@ -14,47 +15,177 @@ impl Plugin for BuildingPlugin {
 #[derive(Component)]
 pub struct BuildingTag;
 
+#[derive(Component)]
+pub struct Building {
+    pub name: String,
+    pub rotation: Rotation
+}
+
+/// Quarter turns anticlockwise from how a building's sprite is drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rotation {
+    #[default]
+    R0,
+    R90,
+    R180,
+    R270,
+}
+
+impl Rotation {
+    pub fn next(&self) -> Self {
+        match self {
+            Rotation::R0 => Rotation::R90,
+            Rotation::R90 => Rotation::R180,
+            Rotation::R180 => Rotation::R270,
+            Rotation::R270 => Rotation::R0,
+        }
+    }
+
+    pub fn angle(&self) -> f32 {
+        match self {
+            Rotation::R0 => 0.0,
+            Rotation::R90 => std::f32::consts::FRAC_PI_2,
+            Rotation::R180 => std::f32::consts::PI,
+            Rotation::R270 => 3.0 * std::f32::consts::FRAC_PI_2,
+        }
+    }
+
+    /// Rotates an offset from the bottom left tile of an unrotated footprint reaching to `max_offset`,
+    /// such that the rotated footprint still has its bottom left tile at offset zero
+    pub fn rotate_offset(&self, offset: IVec2, max_offset: IVec2) -> IVec2 {
+        match self {
+            Rotation::R0 => offset,
+            Rotation::R90 => IVec2::new(max_offset.y - offset.y, offset.x),
+            Rotation::R180 => max_offset - offset,
+            Rotation::R270 => IVec2::new(offset.y, max_offset.x - offset.x),
+        }
+    }
+
+    /// the offset to the top right tile once rotated
+    pub fn rotate_size(&self, max_offset: IVec2) -> IVec2 {
+        match self {
+            Rotation::R0 | Rotation::R180 => max_offset,
+            Rotation::R90 | Rotation::R270 => IVec2::new(max_offset.y, max_offset.x),
+        }
+    }
+}
+
 #[derive(Bundle)]
 pub struct BuildingBundle {
     pub tag: BuildingTag,
+    pub building: Building,
     pub sprite: SpriteSheetBundle,
     pub grid_entity: GridEntity
 }
 
 
+/// whether the building has room at the location. Miners also have to cover an ore deposit
+pub fn building_fits(grid: &Grid, location: IVec2, rotation: Rotation, spawn_info: &BuildingSpawnInfo) -> bool {
+    let max_offset = rotation.rotate_size(spawn_info.max_offset);
+    let mut on_node = false;
+    for x in 0..=max_offset.x {
+        for y in 0..=max_offset.y {
+            let cell = location + IVec2::new(x, y);
+            if !grid.is_free(cell) {return false}
+            if grid[cell].floor == Floor::OreDeposit {on_node = true;}
+        }
+    }
+    on_node || !spawn_info.requires_node
+}
+
 pub fn spawn_building(
     commands: &mut Commands,
     grid: &mut Grid,
     grid_scale: &GridScale,
     location: IVec2,
-    spawn_info: &(usize, String, IVec2),
+    rotation: Rotation,
+    spawn_info: &BuildingSpawnInfo,
     atlas_handle: &BuildingAtlasHandle
-) {
-    for x in 0..=spawn_info.2.x {
-        for y in 0..=spawn_info.2.y {
-            if grid[location + IVec2::new(x, y)] != TileState::Empty {return}
-        }
+) -> Option<Entity> {
+    if !building_fits(grid, location, rotation, spawn_info) {return None}
+    let max_offset = rotation.rotate_size(spawn_info.max_offset);
+    let max = location + max_offset;
+    let grid_entity = GridEntity::new(location, Some(max))
+        .with_interaction_points(spawn_info.interaction_points.iter()
+            .map(|offset| location + rotation.rotate_offset(*offset, spawn_info.max_offset))
+            .collect());
+    let cells = grid_entity.cells.clone();
+    for cell in cells.iter() {
+        grid.set_object(*cell, Some(Object::Building));
     }
-    let max = location + spawn_info.2;
-    let grid_entity = GridEntity::new(location, Some(max));
-    for cell in grid_entity.cells.iter() {
-        grid.tiles.insert(*cell, TileState::Building);
+    for cell in grid_entity.interaction_points.iter() {
+        grid.add_interaction_point(*cell);
     }
-    let offset = Vec2::new(spawn_info.2.x as f32, spawn_info.2.y as f32) * Vec2::splat(grid.tile_size / 2.0);
+    let offset = Vec2::new(max_offset.x as f32, max_offset.y as f32) * Vec2::splat(grid.tile_size / 2.0);
 
-    commands.spawn(BuildingBundle {
+    let entity = commands.spawn(BuildingBundle {
         tag: BuildingTag,
         sprite: SpriteSheetBundle {
             texture_atlas: atlas_handle.0.clone(),
-            sprite: TextureAtlasSprite::new(spawn_info.0),
+            sprite: TextureAtlasSprite::new(spawn_info.sprite_index),
             transform: Transform {
                 translation: (grid_to_space(location, grid) + offset).extend(0.0),
+                rotation: Quat::from_rotation_z(rotation.angle()),
                 scale: grid_scale.0,
-                ..Default::default()
             },
             ..Default::default()
         },
-        grid_entity: grid_entity
+        grid_entity,
+        building: Building {
+            name: spawn_info.name.clone(),
+            rotation
+        }
     })
-    .insert(Name::new(spawn_info.1.clone()));
-}
\ No newline at end of file
+    .insert(Name::new(spawn_info.name.clone()))
+    .id();
+    if spawn_info.requires_node {
+        commands.entity(entity).insert((Miner::default(), Inventory::default()));
+    }
+    grid.set_owner(&cells, Some(entity));
+    Some(entity)
+}
+
+
+#[cfg(test)]
+mod rotation_tests {
+    use super::Rotation;
+    use bevy::prelude::*;
+
+    #[test]
+    fn footprints() {
+        // a 3 wide, 2 tall building with an interaction point left of its bottom left tile
+        let max_offset = IVec2::new(2, 1);
+        let point = IVec2::new(-1, 0);
+
+        assert_eq!(Rotation::R90.rotate_size(max_offset), IVec2::new(1, 2));
+        assert_eq!(Rotation::R90.rotate_offset(IVec2::ZERO, max_offset), IVec2::new(1, 0));
+        assert_eq!(Rotation::R90.rotate_offset(point, max_offset), IVec2::new(1, -1));
+        assert_eq!(Rotation::R180.rotate_offset(point, max_offset), IVec2::new(3, 1));
+        assert_eq!(Rotation::R270.rotate_offset(point, max_offset), IVec2::new(0, 3));
+
+        // every tile of the footprint stays inside the rotated footprint
+        for rotation in [Rotation::R0, Rotation::R90, Rotation::R180, Rotation::R270] {
+            let size = rotation.rotate_size(max_offset);
+            for x in 0..=max_offset.x {
+                for y in 0..=max_offset.y {
+                    let rotated = rotation.rotate_offset(IVec2::new(x, y), max_offset);
+                    assert!(rotated.cmpge(IVec2::ZERO).all() && rotated.cmple(size).all(), "{:?} {}", rotation, rotated);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn full_turn() {
+        let mut rotation = Rotation::R0;
+        let mut offset = IVec2::new(4, -2);
+        let mut max_offset = IVec2::new(2, 1);
+        for _ in 0..4 {
+            offset = Rotation::R90.rotate_offset(offset, max_offset);
+            max_offset = Rotation::R90.rotate_size(max_offset);
+            rotation = rotation.next();
+        }
+        assert_eq!(rotation, Rotation::R0);
+        assert_eq!(offset, IVec2::new(4, -2));
+    }
+}
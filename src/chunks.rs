@@ -0,0 +1,173 @@
+use bevy::{prelude::*, utils::HashMap};
+use core::ops::Index;
+
+/// width and height of a chunk in tiles
+pub const CHUNK_SIZE: i32 = 32;
+const CHUNK_AREA: usize = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+
+#[derive(Clone)]
+struct Chunk<T> {
+    tiles: Box<[T]>,
+    /// how many tiles are not the default, the chunk is dropped once this reaches zero
+    filled: usize,
+}
+
+/// Tiles stored in dense square chunks, so memory only grows with the area that has something in it.
+///
+/// Any tile that was never set reads as `T::default()`, and setting a tile back to the default frees
+/// its chunk once the whole chunk is default again.
+#[derive(Clone)]
+pub struct ChunkedTiles<T> {
+    chunks: HashMap<IVec2, Chunk<T>>,
+    default: T,
+}
+
+impl<T: Copy + Default + PartialEq> Default for ChunkedTiles<T> {
+    fn default() -> Self {
+        ChunkedTiles {
+            chunks: HashMap::new(),
+            default: T::default(),
+        }
+    }
+}
+
+/// the chunk a tile is in and its index inside that chunk
+fn split(cell: IVec2) -> (IVec2, usize) {
+    let chunk = IVec2::new(cell.x.div_euclid(CHUNK_SIZE), cell.y.div_euclid(CHUNK_SIZE));
+    let local = cell - chunk * CHUNK_SIZE;
+    (chunk, (local.y * CHUNK_SIZE + local.x) as usize)
+}
+
+fn join(chunk: IVec2, index: usize) -> IVec2 {
+    chunk * CHUNK_SIZE + IVec2::new(index as i32 % CHUNK_SIZE, index as i32 / CHUNK_SIZE)
+}
+
+impl<T: Copy + Default + PartialEq> ChunkedTiles<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, cell: IVec2) -> T {
+        let (chunk, index) = split(cell);
+        self.chunks.get(&chunk).map_or(self.default, |chunk| chunk.tiles[index])
+    }
+
+    /// sets a tile, returning what was there before
+    pub fn insert(&mut self, cell: IVec2, value: T) -> T {
+        let (chunk_pos, index) = split(cell);
+        if value == self.default && !self.chunks.contains_key(&chunk_pos) {return self.default;}
+
+        let default = self.default;
+        let chunk = self.chunks.entry(chunk_pos).or_insert_with(|| Chunk {
+            tiles: vec![default; CHUNK_AREA].into_boxed_slice(),
+            filled: 0
+        });
+        let old = std::mem::replace(&mut chunk.tiles[index], value);
+        match (old == default, value == default) {
+            (true, false) => chunk.filled += 1,
+            (false, true) => chunk.filled -= 1,
+            _ => {}
+        }
+        if chunk.filled == 0 {self.chunks.remove(&chunk_pos);}
+        old
+    }
+
+    pub fn remove(&mut self, cell: IVec2) -> T {
+        self.insert(cell, self.default)
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// the smallest and largest tile of the loaded chunks, `None` when nothing is loaded
+    pub fn loaded_bounds(&self) -> Option<(IVec2, IVec2)> {
+        let mut chunks = self.chunks.keys();
+        let first = *chunks.next()?;
+        let (min, max) = chunks.fold((first, first), |(min, max), chunk| (min.min(*chunk), max.max(*chunk)));
+        Some((min * CHUNK_SIZE, (max + IVec2::ONE) * CHUNK_SIZE - IVec2::ONE))
+    }
+
+    /// every tile that is not the default
+    pub fn iter(&self) -> impl Iterator<Item = (IVec2, T)> + '_ {
+        self.chunks.iter().flat_map(|(chunk_pos, chunk)| {
+            chunk.tiles.iter().enumerate()
+                .filter(|(_, tile)| **tile != self.default)
+                .map(|(index, tile)| (join(*chunk_pos, index), *tile))
+        })
+    }
+
+    /// every tile that is not the default between `min` and `max` inclusive, only visiting loaded chunks
+    pub fn iter_region(&self, min: IVec2, max: IVec2) -> impl Iterator<Item = (IVec2, T)> + '_ {
+        let (min_chunk, _) = split(min);
+        let (max_chunk, _) = split(max);
+        (min_chunk.x..=max_chunk.x)
+            .flat_map(move |x| (min_chunk.y..=max_chunk.y).map(move |y| IVec2::new(x, y)))
+            .filter_map(|chunk_pos| self.chunks.get(&chunk_pos).map(|chunk| (chunk_pos, chunk)))
+            .flat_map(move |(chunk_pos, chunk)| {
+                let low = (min - chunk_pos * CHUNK_SIZE).max(IVec2::ZERO);
+                let high = (max - chunk_pos * CHUNK_SIZE).min(IVec2::splat(CHUNK_SIZE - 1));
+                (low.y..=high.y).flat_map(move |y| (low.x..=high.x).map(move |x| IVec2::new(x, y)))
+                    .map(move |local| (chunk_pos * CHUNK_SIZE + local, chunk.tiles[(local.y * CHUNK_SIZE + local.x) as usize]))
+            })
+            .filter(|(_, tile)| *tile != self.default)
+    }
+}
+
+impl<T: Copy + Default + PartialEq> Index<IVec2> for ChunkedTiles<T> {
+    type Output = T;
+
+    fn index(&self, index: IVec2) -> &Self::Output {
+        let (chunk, local) = split(index);
+        self.chunks.get(&chunk).map_or(&self.default, |chunk| &chunk.tiles[local])
+    }
+}
+
+
+#[cfg(test)]
+mod chunk_tests {
+    use super::{ChunkedTiles, CHUNK_SIZE};
+    use bevy::prelude::*;
+
+    #[test]
+    fn set_and_get() {
+        let mut tiles = ChunkedTiles::<u8>::new();
+        for cell in [IVec2::new(0, 0), IVec2::new(-1, -1), IVec2::new(31, 32), IVec2::new(-33, 70)] {
+            assert_eq!(tiles.insert(cell, 3), 0);
+            assert_eq!(tiles[cell], 3);
+        }
+        assert_eq!(tiles[IVec2::new(1, 0)], 0);
+        assert_eq!(tiles.chunk_count(), 4);
+        assert_eq!(tiles.loaded_bounds(), Some((IVec2::new(-2 * CHUNK_SIZE, -CHUNK_SIZE), IVec2::new(CHUNK_SIZE - 1, 3 * CHUNK_SIZE - 1))));
+    }
+
+    #[test]
+    fn empty_chunks_are_freed() {
+        let mut tiles = ChunkedTiles::<u8>::new();
+        tiles.insert(IVec2::new(5, 5), 1);
+        tiles.insert(IVec2::new(6, 5), 1);
+        tiles.remove(IVec2::new(5, 5));
+        assert_eq!(tiles.chunk_count(), 1);
+        tiles.insert(IVec2::new(6, 5), 0);
+        assert_eq!(tiles.chunk_count(), 0);
+        assert_eq!(tiles.loaded_bounds(), None);
+        // setting an unloaded tile to the default does not load anything
+        tiles.remove(IVec2::new(100, 100));
+        assert_eq!(tiles.chunk_count(), 0);
+    }
+
+    #[test]
+    fn regions() {
+        let mut tiles = ChunkedTiles::<u8>::new();
+        let cells = [IVec2::new(-40, 0), IVec2::new(-1, 3), IVec2::new(0, 0), IVec2::new(31, 31), IVec2::new(32, 31), IVec2::new(200, 0)];
+        for cell in cells {
+            tiles.insert(cell, 1);
+        }
+
+        let mut found: Vec<IVec2> = tiles.iter_region(IVec2::new(-1, 0), IVec2::new(32, 31)).map(|(cell, _)| cell).collect();
+        found.sort_by_key(|cell| (cell.x, cell.y));
+        assert_eq!(found, vec![IVec2::new(-1, 3), IVec2::new(0, 0), IVec2::new(31, 31), IVec2::new(32, 31)]);
+        assert_eq!(tiles.iter().count(), cells.len());
+    }
+}
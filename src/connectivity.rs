@@ -0,0 +1,378 @@
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+use std::collections::VecDeque;
+
+use crate::{building::BuildingTag, grid::{Grid, GridEntity, GridLayout, TileChangeFlush, TileChanged, HEX_NEIGHBOURS}, robot::Robot, AppState};
+
+/// the region of all the open ground around the built up area
+pub const OUTSIDE: u32 = 0;
+/// the eight tiles around a tile, in order going round
+const RING: [IVec2; 8] = [
+    IVec2::new(0, 1), IVec2::new(1, 1), IVec2::new(1, 0), IVec2::new(1, -1),
+    IVec2::new(0, -1), IVec2::new(-1, -1), IVec2::new(-1, 0), IVec2::new(-1, 1),
+];
+
+const ISOLATED_COLOUR: Color = Color::rgb(1.0, 0.45, 0.45);
+
+
+pub struct ConnectivityPlugin;
+
+impl Plugin for ConnectivityPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ConnectivityRegions>()
+            .add_systems(PostUpdate, (update_connectivity, highlight_isolated_buildings).chain().after(TileChangeFlush).run_if(in_state(AppState::Finished)));
+    }
+}
+
+
+/// Connected component labels for the walkable tiles of the grid.
+///
+/// Only the box around everything that has been built is labelled, with a margin of one tile so that the
+/// outer ring is always open ground. Everything outside the box belongs to the `OUTSIDE` region.
+/// On a grid with bounds the box is the whole map instead, and there is nothing outside it.
+#[derive(Resource, Default)]
+pub struct ConnectivityRegions {
+    labels: HashMap<IVec2, u32>,
+    sizes: HashMap<u32, usize>,
+    bounds: Option<(IVec2, IVec2)>,
+    /// the bounds of the grid when it was last rebuilt
+    map_bounds: Option<(IVec2, IVec2)>,
+    next_label: u32,
+}
+
+impl ConnectivityRegions {
+    fn in_bounds(&self, cell: IVec2) -> bool {
+        self.bounds.is_some_and(|(min, max)| cell.cmpge(min).all() && cell.cmple(max).all())
+    }
+
+    /// the region a walkable tile is in, `None` for tiles that cannot be walked on
+    pub fn label(&self, cell: IVec2) -> Option<u32> {
+        if self.in_bounds(cell) {
+            self.labels.get(&cell).copied()
+        } else if self.map_bounds.is_some() {
+            None
+        } else {
+            Some(OUTSIDE)
+        }
+    }
+
+    pub fn is_reachable(&self, a: IVec2, b: IVec2) -> bool {
+        matches!((self.label(a), self.label(b)), (Some(x), Some(y)) if x == y)
+    }
+
+    /// relabels everything from scratch
+    pub fn rebuild(&mut self, grid: &Grid) {
+        self.labels.clear();
+        self.sizes.clear();
+        self.next_label = OUTSIDE + 1;
+        self.map_bounds = grid.bounds();
+
+        let (min, max) = match grid.bounds() {
+            Some(bounds) => bounds,
+            None => {
+                let mut blocked = grid.tiles().iter().map(|(cell, _)| cell).filter(|cell| !grid.is_walkable(*cell));
+                let Some(first) = blocked.next() else {
+                    self.bounds = None;
+                    return;
+                };
+                blocked.fold((first, first), |(min, max), cell| (min.min(cell), max.max(cell)))
+            }
+        };
+        self.bounds = Some((min - IVec2::ONE, max + IVec2::ONE));
+
+        // the margin around a map is off the edge, so isn't open ground
+        if self.map_bounds.is_none() {
+            let outside_size = self.flood(min - IVec2::ONE, OUTSIDE, grid, |_| true);
+            self.sizes.insert(OUTSIDE, outside_size);
+        }
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                let cell = IVec2::new(x, y);
+                if self.labels.contains_key(&cell) || !grid.is_walkable(cell) {continue;}
+                let label = self.new_label();
+                let size = self.flood(cell, label, grid, |_| true);
+                self.sizes.insert(label, size);
+            }
+        }
+    }
+
+    /// brings the labels up to date after a tile may have changed between walkable and not
+    pub fn update_cell(&mut self, cell: IVec2, grid: &Grid) {
+        let walkable = grid.is_walkable(cell);
+        let inside = self.bounds.is_some_and(|(min, max)| cell.cmpgt(min).all() && cell.cmplt(max).all());
+        if !inside {
+            // the box has to grow (or the tile is on its open outer ring)
+            if !walkable || self.in_bounds(cell) {self.rebuild(grid);}
+            return;
+        }
+
+        match (walkable, self.labels.get(&cell).copied()) {
+            (true, None) => self.open_cell(cell, grid),
+            (false, Some(label)) => self.close_cell(cell, label, grid),
+            _ => {}
+        }
+    }
+
+    /// a tile became walkable, joining every region around it
+    fn open_cell(&mut self, cell: IVec2, grid: &Grid) {
+        let mut around: Vec<u32> = grid.layout.neighbours().iter().filter_map(|dir| self.labels.get(&(cell + *dir)).copied()).collect();
+        around.sort();
+        around.dedup();
+
+        let Some(keep) = around.iter().copied().max_by_key(|label| if *label == OUTSIDE {usize::MAX} else {self.sizes[label]}) else {
+            let label = self.new_label();
+            self.labels.insert(cell, label);
+            self.sizes.insert(label, 1);
+            return;
+        };
+
+        self.labels.insert(cell, keep);
+        *self.sizes.get_mut(&keep).unwrap() += 1;
+        for label in around.into_iter().filter(|label| *label != keep) {
+            let start = grid.layout.neighbours().iter().map(|dir| cell + *dir).find(|next| self.labels.get(next) == Some(&label)).unwrap();
+            let moved = self.flood(start, keep, grid, |other| other == Some(label));
+            self.sizes.remove(&label);
+            *self.sizes.get_mut(&keep).unwrap() += moved;
+        }
+    }
+
+    /// a tile stopped being walkable, which may cut its region in two
+    fn close_cell(&mut self, cell: IVec2, label: u32, grid: &Grid) {
+        self.labels.remove(&cell);
+        *self.sizes.get_mut(&label).unwrap() -= 1;
+
+        let neighbours: Vec<IVec2> = grid.layout.neighbours().iter().map(|dir| cell + *dir).filter(|next| self.labels.contains_key(next)).collect();
+        if neighbours.len() < 2 || still_joined_locally(cell, grid) {return;}
+
+        // flood each side with a fresh label, then give the original back to the outside facing (or first) part
+        let mut parts = Vec::new();
+        for start in neighbours {
+            if self.labels.get(&start) != Some(&label) {continue;}
+            let part = self.new_label();
+            let size = self.flood(start, part, grid, |other| other == Some(label));
+            parts.push((part, start, size));
+        }
+        let keep = parts.iter().position(|(_, start, _)| label != OUTSIDE || self.touches_edge(*start, grid)).unwrap_or(0);
+        self.sizes.remove(&label);
+        for (i, (part, start, size)) in parts.into_iter().enumerate() {
+            if i == keep {
+                self.flood(start, label, grid, |other| other == Some(part));
+            }
+            self.sizes.insert(if i == keep {label} else {part}, size);
+        }
+    }
+
+    fn touches_edge(&self, start: IVec2, grid: &Grid) -> bool {
+        let Some((min, _)) = self.bounds else {return false};
+        let label = self.labels.get(&start).copied();
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(current) = queue.pop_front() {
+            if current.cmpeq(min).any() || !self.in_bounds(current + IVec2::ONE) {return true;}
+            for dir in grid.layout.neighbours() {
+                let next = current + *dir;
+                if grid.is_walkable(next) && self.labels.get(&next).copied() == label && visited.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        false
+    }
+
+    /// labels every walkable tile connected to `start` whose current label passes the filter, returns how many
+    fn flood(&mut self, start: IVec2, label: u32, grid: &Grid, filter: impl Fn(Option<u32>) -> bool) -> usize {
+        let mut queue = VecDeque::from([start]);
+        self.labels.insert(start, label);
+        let mut count = 1;
+
+        while let Some(current) = queue.pop_front() {
+            for dir in grid.layout.neighbours() {
+                let next = current + *dir;
+                if !self.in_bounds(next) || !grid.is_walkable(next) {continue;}
+                let existing = self.labels.get(&next).copied();
+                if existing == Some(label) || !filter(existing) {continue;}
+                self.labels.insert(next, label);
+                count += 1;
+                queue.push_back(next);
+            }
+        }
+        count
+    }
+
+    fn new_label(&mut self) -> u32 {
+        self.next_label += 1;
+        self.next_label
+    }
+}
+
+/// Whether the open tiles next to a newly blocked tile are still joined by going round the tiles around it.
+/// When they are the region cannot have been split, which saves flooding it.
+fn still_joined_locally(cell: IVec2, grid: &Grid) -> bool {
+    // on a square grid the ring has the diagonals between the four neighbours, the six hex neighbours already touch
+    let (ring, neighbour_every): (&[IVec2], usize) = match grid.layout {
+        GridLayout::Square => (&RING, 2),
+        GridLayout::Hex => (&HEX_NEIGHBOURS, 1),
+    };
+    let open: Vec<bool> = ring.iter().map(|offset| grid.is_walkable(cell + *offset)).collect();
+    // start walking the ring just after a blocked tile, so no run of open tiles wraps round the end
+    let Some(first_blocked) = open.iter().position(|walkable| !walkable) else {return true};
+
+    let mut runs_with_neighbours = 0;
+    let mut run_has_neighbour = false;
+    for i in 1..=ring.len() {
+        let index = (first_blocked + i) % ring.len();
+        if open[index] {
+            run_has_neighbour |= index % neighbour_every == 0;
+        } else {
+            if run_has_neighbour {runs_with_neighbours += 1;}
+            run_has_neighbour = false;
+        }
+    }
+    runs_with_neighbours <= 1
+}
+
+
+pub fn update_connectivity(
+    mut regions: ResMut<ConnectivityRegions>,
+    mut tile_changes: EventReader<TileChanged>,
+    grid: Res<Grid>,
+) {
+    if regions.map_bounds != grid.bounds() {
+        tile_changes.clear();
+        regions.rebuild(&grid);
+        return;
+    }
+    for change in tile_changes.read() {
+        if change.walkability_changed() {regions.update_cell(change.pos, &grid);}
+    }
+}
+
+/// tints buildings that no robot can get to
+pub fn highlight_isolated_buildings(
+    regions: Res<ConnectivityRegions>,
+    robot_query: Query<&Robot>,
+    mut building_query: Query<(&GridEntity, &mut TextureAtlasSprite), With<BuildingTag>>,
+) {
+    for (grid_entity, mut sprite) in building_query.iter_mut() {
+        let reachable = robot_query.iter().any(|robot| {
+            grid_entity.interaction_points.iter().any(|point| regions.is_reachable(robot.location, *point))
+        });
+        let colour = if reachable || grid_entity.interaction_points.is_empty() {Color::WHITE} else {ISOLATED_COLOUR};
+        if sprite.color != colour {sprite.color = colour;}
+    }
+}
+
+
+#[cfg(test)]
+mod connectivity_tests {
+    use super::{ConnectivityRegions, OUTSIDE};
+    use crate::{ascii_map::parse_ascii_map, grid::{Grid, Object}};
+    use bevy::{prelude::*, utils::HashMap};
+
+    /// every pair of walkable tiles in the area must agree with a full relabel about being connected
+    fn assert_matches_rebuild(regions: &ConnectivityRegions, grid: &Grid, min: IVec2, max: IVec2) {
+        let mut fresh = ConnectivityRegions::default();
+        fresh.rebuild(grid);
+        let mut mapping = HashMap::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                let cell = IVec2::new(x, y);
+                assert_eq!(regions.label(cell).is_some(), fresh.label(cell).is_some(), "walkability of {}", cell);
+                let (Some(ours), Some(theirs)) = (regions.label(cell), fresh.label(cell)) else {continue};
+                assert_eq!(*mapping.entry(theirs).or_insert(ours), ours, "{} is in the wrong region", cell);
+            }
+        }
+        // no two fresh regions may share one of ours
+        let mut ours: Vec<u32> = mapping.values().copied().collect();
+        ours.sort();
+        ours.dedup();
+        assert_eq!(ours.len(), mapping.len());
+    }
+
+    #[test]
+    fn rooms() {
+        let map = parse_ascii_map("\
+            #######\n\
+            #S.#..#\n\
+            #..#.E#\n\
+            #######\n").unwrap();
+        let mut regions = ConnectivityRegions::default();
+        regions.rebuild(&map.grid);
+
+        let (start, end) = (map.start.unwrap(), map.end.unwrap());
+        assert!(!regions.is_reachable(start, end));
+        assert!(regions.is_reachable(start, start + IVec2::X));
+        assert_eq!(regions.label(IVec2::new(40, 40)), Some(OUTSIDE));
+        assert_eq!(regions.label(IVec2::new(0, 0)), None);
+    }
+
+    #[test]
+    fn incremental_updates() {
+        let mut grid = parse_ascii_map("\
+            ..........\n\
+            .########.\n\
+            .#......#.\n\
+            .#......#.\n\
+            .########.\n\
+            ..........\n").unwrap().grid;
+        let mut regions = ConnectivityRegions::default();
+        regions.rebuild(&grid);
+        let inside = IVec2::new(3, 2);
+        assert!(!regions.is_reachable(inside, IVec2::new(-10, 0)));
+
+        // open a door, then split the room in half, then close the door again
+        let edits = [
+            (IVec2::new(1, 2), None),
+            (IVec2::new(5, 2), Some(Object::Wall)),
+            (IVec2::new(5, 3), Some(Object::Wall)),
+            (IVec2::new(1, 2), Some(Object::Wall)),
+            (IVec2::new(5, 3), None),
+            (IVec2::new(12, 12), Some(Object::Wall)),
+            (IVec2::new(0, 0), Some(Object::Building)),
+        ];
+        for (cell, object) in edits {
+            grid.set_object(cell, object);
+            regions.update_cell(cell, &grid);
+            assert_matches_rebuild(&regions, &grid, IVec2::splat(-3), IVec2::splat(14));
+        }
+        assert!(!regions.is_reachable(inside, IVec2::new(-10, 0)));
+        assert!(regions.is_reachable(inside, IVec2::new(6, 2)));
+    }
+
+    #[test]
+    fn splitting_the_outside() {
+        let mut grid = Grid::new(Vec2::ZERO, 1.0);
+        let mut regions = ConnectivityRegions::default();
+        // build a closed ring a tile at a time, the last wall cuts the middle off
+        let ring: Vec<IVec2> = (0..4).map(|x| IVec2::new(x, 0))
+            .chain((1..4).map(|y| IVec2::new(3, y)))
+            .chain((0..3).rev().map(|x| IVec2::new(x, 3)))
+            .chain((1..3).rev().map(|y| IVec2::new(0, y)))
+            .collect();
+        for cell in ring {
+            grid.set_object(cell, Some(Object::Wall));
+            regions.update_cell(cell, &grid);
+            assert_matches_rebuild(&regions, &grid, IVec2::splat(-3), IVec2::splat(6));
+        }
+        assert!(!regions.is_reachable(IVec2::new(1, 1), IVec2::new(-5, -5)));
+        assert!(regions.is_reachable(IVec2::new(1, 1), IVec2::new(2, 2)));
+    }
+
+    #[test]
+    fn map_edges() {
+        let mut grid = Grid::new(Vec2::ZERO, 1.0).with_bounds(IVec2::ZERO, IVec2::new(9, 4));
+        for y in 0..5 {grid.set_object(IVec2::new(4, y), Some(Object::Wall));}
+        let mut regions = ConnectivityRegions::default();
+        regions.rebuild(&grid);
+
+        // the only way round the wall is off the edge of the map
+        assert!(!regions.is_reachable(IVec2::ZERO, IVec2::new(9, 0)));
+        assert_eq!(regions.label(IVec2::new(-1, 0)), None);
+        assert_eq!(regions.label(IVec2::new(40, 40)), None);
+
+        grid.set_object(IVec2::new(4, 2), None);
+        regions.update_cell(IVec2::new(4, 2), &grid);
+        assert!(regions.is_reachable(IVec2::ZERO, IVec2::new(9, 0)));
+        assert_matches_rebuild(&regions, &grid, IVec2::splat(-2), IVec2::new(11, 6));
+    }
+}
@@ -0,0 +1,213 @@
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{
+    asset_loading::DoorAtlasHandle,
+    grid::{grid_to_space, Grid, GridEntity, GridScale, Object, TileChanged, TileChangeFlush},
+    interaction::TileSelectIndicator,
+    AppState
+};
+
+/// frames from shut to fully open in each row of `doors.png`
+pub const DOOR_FRAMES: usize = 4;
+/// seconds between frames while a door opens or shuts
+const DOOR_FRAME_SECONDS: f32 = 0.06;
+const LOCKED_TINT: Color = Color::rgb(1.0, 0.55, 0.55);
+
+
+pub struct DoorPlugin;
+
+impl Plugin for DoorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, (toggle_door_under_cursor, update_door_tiles, animate_doors).chain().run_if(in_state(AppState::Finished)))
+            .add_systems(PostUpdate, orient_doors.after(TileChangeFlush).run_if(in_state(AppState::Finished)));
+    }
+}
+
+
+/// How a door lets robots through. New doors let robots through and are unlocked
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Door {
+    /// doors that don't are as good as walls to robots
+    pub admits_robots: bool,
+    /// shut for everyone until unlocked, by a script or with K
+    pub locked: bool,
+}
+
+impl Default for Door {
+    fn default() -> Self {
+        Door {admits_robots: true, locked: false}
+    }
+}
+
+impl Door {
+    pub fn is_passable(&self) -> bool {
+        self.admits_robots && !self.locked
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match (self.locked, self.admits_robots) {
+            (true, _) => "Door (locked)",
+            (false, true) => "Door",
+            (false, false) => "Door (closed to robots)",
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct DoorAnimation {
+    /// 0 is shut
+    pub frame: usize,
+    /// whether it joins walls above and below rather than either side
+    pub vertical: bool,
+    pub timer: Timer,
+}
+
+pub fn spawn_door(
+    commands: &mut Commands,
+    grid: &mut Grid,
+    grid_scale: &GridScale,
+    location: IVec2,
+    door: Door,
+    atlas_handle: &DoorAtlasHandle
+) -> Option<Entity> {
+    if !grid.is_free(location) {return None}
+
+    let entity = commands.spawn((
+        SpriteSheetBundle {
+            texture_atlas: atlas_handle.0.clone(),
+            sprite: TextureAtlasSprite::new(0),
+            transform: Transform {
+                translation: grid_to_space(location, grid).extend(0.0),
+                scale: grid_scale.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        GridEntity::new(location, None),
+        door,
+        DoorAnimation {frame: 0, vertical: false, timer: Timer::from_seconds(DOOR_FRAME_SECONDS, TimerMode::Repeating)},
+        Name::new("Door")
+    ))
+    .id();
+    grid.set_object(location, Some(Object::Door {passable: door.is_passable()}));
+    grid.set_owner(&[location], Some(entity));
+    Some(entity)
+}
+
+/// Locks or unlocks the door at `pos`, returning false when there is no door there
+pub fn set_door_locked(grid: &Grid, door_query: &mut Query<&mut Door>, pos: IVec2, locked: bool) -> bool {
+    let Some(mut door) = grid.owner(pos).and_then(|owner| door_query.get_mut(owner).ok()) else {return false};
+    if door.locked != locked {door.locked = locked;}
+    true
+}
+
+/// K locks or unlocks the door under the cursor, Shift+K changes whether it lets robots through
+pub fn toggle_door_under_cursor(
+    keyboard: Res<Input<KeyCode>>,
+    tile_select: Query<&TileSelectIndicator>,
+    grid: Res<Grid>,
+    mut door_query: Query<&mut Door>
+) {
+    if !keyboard.just_pressed(KeyCode::K) {return;}
+    let Some(mut door) = grid.owner(tile_select.single().pos).and_then(|owner| door_query.get_mut(owner).ok()) else {return};
+    if keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        door.admits_robots = !door.admits_robots;
+    } else {
+        door.locked = !door.locked;
+    }
+}
+
+/// Keeps the grid in step with doors being locked or changing who they let through, so paths are planned again
+pub fn update_door_tiles(
+    mut grid: ResMut<Grid>,
+    door_query: Query<(&GridEntity, &Door), Changed<Door>>
+) {
+    for (grid_entity, door) in door_query.iter() {
+        let object = Some(Object::Door {passable: door.is_passable()});
+        if grid[grid_entity.min].object != object {grid.set_object(grid_entity.min, object);}
+    }
+}
+
+/// Slides doors open while a robot is on or next to them, and shut again once they have gone
+pub fn animate_doors(
+    time: Res<Time>,
+    grid: Res<Grid>,
+    mut door_query: Query<(&GridEntity, &Door, &mut DoorAnimation, &mut TextureAtlasSprite)>
+) {
+    for (grid_entity, door, mut animation, mut sprite) in door_query.iter_mut() {
+        let pos = grid_entity.min;
+        let robot_near = std::iter::once(pos).chain(grid.layout.neighbours().iter().map(|offset| pos + *offset))
+            .any(|cell| grid[cell].occupant.is_some());
+        let target = if robot_near && door.is_passable() {DOOR_FRAMES - 1} else {0};
+
+        if animation.frame != target && animation.timer.tick(time.delta()).just_finished() {
+            animation.frame = if animation.frame < target {animation.frame + 1} else {animation.frame - 1};
+        }
+        let index = animation.vertical as usize * DOOR_FRAMES + animation.frame;
+        if sprite.index != index {sprite.index = index;}
+        let tint = if door.locked {LOCKED_TINT} else {Color::WHITE};
+        if sprite.color != tint {sprite.color = tint;}
+    }
+}
+
+/// Whether a door at `pos` should join walls above and below it. Doors face across unless walls only meet them from
+/// the rows above or below, which on hex maps is any neighbour but the two in the same row
+pub fn faces_vertically(grid: &Grid, pos: IVec2) -> bool {
+    let (across, along): (Vec<IVec2>, Vec<IVec2>) = grid.layout.neighbours().iter().partition(|offset| offset.y == 0);
+    let joins = |offset: &IVec2| grid[pos + *offset].object.is_some_and(|object| object.joins_walls());
+    along.iter().any(joins) && !across.iter().any(joins)
+}
+
+/// Turns doors to line up with the walls next to them
+pub fn orient_doors(
+    mut door_query: Query<(&GridEntity, &mut DoorAnimation)>,
+    mut tile_changes: EventReader<TileChanged>,
+    grid: Res<Grid>
+) {
+    let neighbours = grid.layout.neighbours();
+    let affected: HashSet<IVec2> = tile_changes.read()
+        .flat_map(|change| std::iter::once(change.pos).chain(neighbours.iter().map(move |offset| change.pos + *offset)))
+        .collect();
+    if affected.is_empty() {return;}
+
+    for (grid_entity, mut animation) in door_query.iter_mut() {
+        if !affected.contains(&grid_entity.min) {continue;}
+        let vertical = faces_vertically(&grid, grid_entity.min);
+        if animation.vertical != vertical {animation.vertical = vertical;}
+    }
+}
+
+
+#[cfg(test)]
+mod door_tests {
+    use super::{faces_vertically, Door};
+    use crate::grid::{Grid, GridLayout, Object};
+    use bevy::prelude::{IVec2, Vec2};
+
+    #[test]
+    fn passable_doors_are_walkable() {
+        let mut grid = Grid::new(Vec2::ZERO, 1.0);
+        for door in [Door::default(), Door {admits_robots: false, locked: false}, Door {admits_robots: true, locked: true}] {
+            grid.set_object(IVec2::ZERO, Some(Object::Door {passable: door.is_passable()}));
+            assert_eq!(grid.is_walkable(IVec2::ZERO), door == Door::default());
+        }
+        assert!(!grid.is_free(IVec2::ZERO));
+    }
+
+    #[test]
+    fn orientation() {
+        for layout in [GridLayout::Square, GridLayout::Hex] {
+            let mut grid = Grid::new(Vec2::ZERO, 1.0).with_layout(layout);
+            grid.set_object(IVec2::new(0, -1), Some(Object::Wall));
+            assert!(faces_vertically(&grid, IVec2::ZERO));
+            grid.set_object(IVec2::new(-1, 0), Some(Object::Wall));
+            assert!(!faces_vertically(&grid, IVec2::ZERO));
+        }
+
+        // a wall on one of the hex diagonals is in the row above
+        let mut grid = Grid::new(Vec2::ZERO, 1.0).with_layout(GridLayout::Hex);
+        grid.set_object(IVec2::new(-1, 1), Some(Object::Wall));
+        assert!(faces_vertically(&grid, IVec2::ZERO));
+    }
+}
@@ -0,0 +1,166 @@
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+use std::collections::VecDeque;
+
+use crate::{grid::{grid_to_space, Fog, FogChanged, Grid, GridLayout, TileChangeFlush, HEX_ROW_HEIGHT}, robot::{track_occupants, Robot}, AppState};
+
+/// how many steps away from a robot tiles can be seen
+pub const SENSOR_RADIUS: u32 = 6;
+const UNEXPLORED_COLOUR: Color = Color::rgba(0.0, 0.0, 0.0, 0.85);
+const EXPLORED_COLOUR: Color = Color::rgba(0.0, 0.0, 0.0, 0.45);
+
+
+pub struct FogPlugin;
+
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, update_fog.after(track_occupants).run_if(in_state(AppState::Finished)))
+            .add_systems(PostUpdate, draw_fog.after(TileChangeFlush).run_if(in_state(AppState::Finished)));
+    }
+}
+
+/// darkens one tile of the map
+#[derive(Component)]
+pub struct FogTile(pub IVec2);
+
+/// every tile within `radius` steps of `centre`
+pub fn tiles_in_range(layout: GridLayout, centre: IVec2, radius: u32) -> impl Iterator<Item = IVec2> {
+    let r = radius as i32;
+    (-r..=r).flat_map(move |x| (-r..=r).map(move |y| centre + IVec2::new(x, y)))
+        .filter(move |pos| layout.distance(centre, *pos) <= radius)
+}
+
+/// Marks the tiles around robots as visible, and the ones they have moved away from as explored
+pub fn update_fog(
+    mut grid: ResMut<Grid>,
+    robot_query: Query<&Robot>,
+    moved: Query<(), Changed<Robot>>,
+    mut removed: RemovedComponents<Robot>,
+    mut visible: Local<HashSet<IVec2>>
+) {
+    let any_removed = removed.read().count() > 0;
+    if moved.is_empty() && !any_removed {return;}
+
+    let now: HashSet<IVec2> = robot_query.iter()
+        .flat_map(|robot| tiles_in_range(grid.layout, robot.location, SENSOR_RADIUS))
+        .filter(|pos| grid.in_bounds(*pos))
+        .collect();
+    // only set when something changes, so each changed tile is redrawn once
+    for pos in visible.difference(&now) {
+        if grid.fog(*pos) == Fog::Visible {grid.set_fog(*pos, Fog::Explored);}
+    }
+    for pos in now.iter() {
+        if grid.fog(*pos) != Fog::Visible {grid.set_fog(*pos, Fog::Visible);}
+    }
+    *visible = now;
+}
+
+fn fog_colour(fog: Fog) -> Color {
+    match fog {
+        Fog::Unexplored => UNEXPLORED_COLOUR,
+        Fog::Explored => EXPLORED_COLOUR,
+        Fog::Visible => Color::NONE,
+    }
+}
+
+/// Keeps a darkening sprite over every tile of the map, spawning them again whenever its bounds change.
+/// Otherwise only the sprites of tiles whose fog changed are touched.
+/// Maps without edges have no overlay, as there would be no end to it
+pub fn draw_fog(
+    mut commands: Commands,
+    grid: Res<Grid>,
+    mut changes: EventReader<FogChanged>,
+    mut sprites: Query<&mut Sprite, With<FogTile>>,
+    mut drawn: Local<(Option<(IVec2, IVec2)>, GridLayout)>,
+    mut tiles: Local<HashMap<IVec2, Entity>>
+) {
+    if *drawn == (grid.bounds(), grid.layout) {
+        for change in changes.read() {
+            let Some(mut sprite) = tiles.get(&change.pos).and_then(|entity| sprites.get_mut(*entity).ok()) else {continue};
+            sprite.color = fog_colour(change.fog);
+        }
+        return;
+    }
+
+    // the new sprites are coloured from the grid, so the changes so far are already in them
+    changes.clear();
+    *drawn = (grid.bounds(), grid.layout);
+    for (_, entity) in tiles.drain() {
+        commands.entity(entity).despawn();
+    }
+    let Some((min, max)) = grid.bounds() else {return};
+    // hex rows overlap, so a tile's rectangle is only as tall as the gap between rows
+    let size = match grid.layout {
+        GridLayout::Square => Vec2::splat(grid.tile_size),
+        GridLayout::Hex => Vec2::new(grid.tile_size, grid.tile_size * HEX_ROW_HEIGHT),
+    };
+    for pos in (min.x..=max.x).flat_map(|x| (min.y..=max.y).map(move |y| IVec2::new(x, y))) {
+        let entity = commands.spawn((SpriteBundle {
+            sprite: Sprite {
+                color: fog_colour(grid.fog(pos)),
+                custom_size: Some(size),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(grid_to_space(pos, &grid).extend(0.5)),
+            ..Default::default()
+        }, FogTile(pos))).id();
+        tiles.insert(pos, entity);
+    }
+}
+
+/// The closest explored tile next to an unexplored one that a robot at `start` can walk to
+pub fn nearest_frontier(grid: &Grid, start: IVec2) -> Option<IVec2> {
+    let mut queue = VecDeque::from([start]);
+    let mut seen = HashSet::from([start]);
+    while let Some(pos) = queue.pop_front() {
+        let neighbours = grid.layout.neighbours().iter().map(|offset| pos + *offset);
+        if neighbours.clone().any(|next| grid.in_bounds(next) && !grid.is_explored(next)) {return Some(pos);}
+        for next in neighbours {
+            if grid.is_walkable(next) && grid.is_explored(next) && seen.insert(next) {queue.push_back(next);}
+        }
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod fog_tests {
+    use super::{nearest_frontier, tiles_in_range};
+    use crate::grid::{Fog, Grid, GridLayout, Object};
+    use bevy::prelude::{IVec2, Vec2};
+
+    fn explore(grid: &mut Grid, min: IVec2, max: IVec2) {
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                grid.set_fog(IVec2::new(x, y), Fog::Explored);
+            }
+        }
+    }
+
+    #[test]
+    fn sensor_range() {
+        assert_eq!(tiles_in_range(GridLayout::Square, IVec2::ZERO, 2).count(), 13);
+        assert_eq!(tiles_in_range(GridLayout::Hex, IVec2::ZERO, 2).count(), 19);
+        let mut ring: Vec<IVec2> = tiles_in_range(GridLayout::Hex, IVec2::new(5, 5), 1).map(|pos| pos - IVec2::new(5, 5)).collect();
+        ring.retain(|offset| *offset != IVec2::ZERO);
+        assert!(ring.len() == 6 && GridLayout::Hex.neighbours().iter().all(|offset| ring.contains(offset)));
+    }
+
+    #[test]
+    fn frontiers() {
+        let mut grid = Grid::new(Vec2::ZERO, 1.0).with_bounds(IVec2::ZERO, IVec2::new(9, 9));
+        explore(&mut grid, IVec2::ZERO, IVec2::new(4, 9));
+        assert_eq!(nearest_frontier(&grid, IVec2::new(4, 5)), Some(IVec2::new(4, 5)));
+        assert_eq!(nearest_frontier(&grid, IVec2::new(1, 5)), Some(IVec2::new(4, 5)));
+
+        // the near edge is walled off, so the robot has to go round
+        for y in 0..9 {
+            grid.set_object(IVec2::new(3, y), Some(Object::Wall));
+        }
+        assert_eq!(nearest_frontier(&grid, IVec2::new(1, 0)), Some(IVec2::new(4, 9)));
+
+        // the edge of the map is not a frontier
+        explore(&mut grid, IVec2::ZERO, IVec2::new(9, 9));
+        assert_eq!(nearest_frontier(&grid, IVec2::new(1, 0)), None);
+    }
+}
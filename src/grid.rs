@@ -1,15 +1,26 @@
-use bevy::{prelude::*, utils::HashMap};
+use bevy::prelude::*;
 use core::ops::Index;
-use crate::AppState;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use crate::{chunks::ChunkedTiles, AppState};
 
 
 const SPRITE_TILE_SIZE: f32 = 50.0;
+const MAP_CONFIG_PATH: &str = "assets/map.ron";
+const BORDER_COLOUR: Color = Color::rgb(0.85, 0.8, 0.3);
 pub struct GridPlugin;
 
 impl Plugin for GridPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_systems(OnEnter(AppState::Setup), spawn_grid);
+            .add_event::<TileChanged>()
+            .add_event::<FogChanged>()
+            .add_systems(OnEnter(AppState::Setup), spawn_grid)
+            .add_systems(Update, draw_map_border.run_if(in_state(AppState::Finished)))
+            .add_systems(PostUpdate, flush_tile_changes.in_set(TileChangeFlush).run_if(in_state(AppState::Finished)));
+
+        #[cfg(debug_assertions)]
+        app.add_systems(PostUpdate, check_owner_index.run_if(in_state(AppState::Finished)));
     }
 }
 
@@ -17,7 +28,9 @@ impl Plugin for GridPlugin {
 pub struct GridEntity {
     pub min: IVec2,
     pub max: IVec2,
-    pub cells: Vec<IVec2>
+    pub cells: Vec<IVec2>,
+    /// tiles outside the entity that robots stand on to use it
+    pub interaction_points: Vec<IVec2>
 }
 
 impl GridEntity {
@@ -33,39 +46,216 @@ impl GridEntity {
                     cells.push(IVec2::new(x, y));
                 }
             }
-            return GridEntity {
+            GridEntity {
                 min,
                 max: max_point,
-                cells: cells
-            };
+                cells,
+                interaction_points: Vec::new()
+            }
         } else {
-            return GridEntity {
+            GridEntity {
                 min,
                 max: min,
-                cells: vec![min]
-            };
+                cells: vec![min],
+                interaction_points: Vec::new()
+            }
         }
     }
 
+    pub fn with_interaction_points(mut self, interaction_points: Vec<IVec2>) -> Self {
+        self.interaction_points = interaction_points;
+        self
+    }
+
 }
 
 
 
 
-#[derive(PartialEq)]
-pub enum TileState {
-    Empty,
+/// What the ground of a tile is made of. Every kind of floor can be walked over
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Floor {
+    #[default]
+    Ground,
+    /// ground with ore in it, miners have to be built on it
+    OreDeposit,
+    /// an ore deposit that has been mined out
+    DepletedOre,
+}
+
+/// Something fixed in place on a tile, which robots can't walk through
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Object {
     Wall,
     Building,
-    Robot,
-    InteractionPoint,
+    /// natural obstacle that can't be built on or removed
+    Rock,
+    /// joins up with walls, robots can only walk through while it is passable
+    Door { passable: bool },
+}
+
+impl Object {
+    /// whether walls next to it connect to it
+    pub fn joins_walls(&self) -> bool {
+        matches!(self, Object::Wall | Object::Door {..})
+    }
 }
 
+/// Everything on one tile, in layers from the ground up
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct Tile {
+    pub floor: Floor,
+    pub object: Option<Object>,
+    /// the robot standing on the tile
+    pub occupant: Option<Entity>,
+    /// how many buildings robots use from this tile
+    pub interaction_points: u8,
+}
 
-#[derive(Resource)]
+impl Tile {
+    /// Every floor can be walked over, robots only get in each other's way through reservations and markers
+    /// are only information, so it comes down to whether something is built on the tile and if it is an open door
+    pub fn is_walkable(&self) -> bool {
+        match self.object {
+            None => true,
+            Some(Object::Door {passable}) => passable,
+            Some(_) => false,
+        }
+    }
+
+    pub fn is_interaction_point(&self) -> bool {
+        self.interaction_points > 0
+    }
+}
+
+/// How much the player knows about a tile. Kept apart from the tile layers, as seeing a tile doesn't change it
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Fog {
+    /// never seen by a robot, nothing can be built there
+    #[default]
+    Unexplored,
+    /// seen before but no robot is near enough to see it now
+    Explored,
+    /// within the sensor radius of a robot
+    Visible,
+}
+
+
+/// Sent once a frame for every tile whose state changed
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub struct TileChanged {
+    pub pos: IVec2,
+    pub old: Tile,
+    pub new: Tile,
+}
+
+impl TileChanged {
+    pub fn walkability_changed(&self) -> bool {
+        self.old.is_walkable() != self.new.is_walkable()
+    }
+}
+
+/// Sent alongside `TileChanged` for every tile whose fog changed
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub struct FogChanged {
+    pub pos: IVec2,
+    pub fog: Fog,
+}
+
+/// Systems reacting to `TileChanged` in `PostUpdate` run after this, so they see every change made this frame
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TileChangeFlush;
+
+/// the four neighbours of a square tile, going anticlockwise from the right
+pub const SQUARE_NEIGHBOURS: [IVec2; 4] = [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y];
+/// the six neighbours of a hex tile in axial coordinates, going anticlockwise from the right
+pub const HEX_NEIGHBOURS: [IVec2; 6] = [
+    IVec2::new(1, 0), IVec2::new(0, 1), IVec2::new(-1, 1),
+    IVec2::new(-1, 0), IVec2::new(0, -1), IVec2::new(1, -1),
+];
+
+/// The shape of the tiles
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GridLayout {
+    #[default]
+    Square,
+    /// Pointy topped hexagons in axial coordinates, x is the column (q) and y the row (r).
+    /// Each row is shifted half a tile right of the one below it
+    Hex,
+}
+
+impl GridLayout {
+    /// offsets to the tiles sharing an edge with a tile, in order going round it
+    pub fn neighbours(&self) -> &'static [IVec2] {
+        match self {
+            GridLayout::Square => &SQUARE_NEIGHBOURS,
+            GridLayout::Hex => &HEX_NEIGHBOURS,
+        }
+    }
+
+    /// the fewest steps between two tiles
+    pub fn distance(&self, a: IVec2, b: IVec2) -> u32 {
+        let d = a - b;
+        match self {
+            GridLayout::Square => (d.x.abs() + d.y.abs()) as u32,
+            GridLayout::Hex => ((d.x.abs() + d.y.abs() + (d.x + d.y).abs()) / 2) as u32,
+        }
+    }
+}
+
+/// The size and placement of the map, loaded from `assets/map.ron`
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct MapConfig {
+    /// in tiles
+    pub width: i32,
+    pub height: i32,
+    pub tile_size: f32,
+    /// where the middle of tile (0, 0) is drawn, the map is centred on that tile
+    pub origin: (f32, f32),
+    #[serde(default)]
+    pub layout: GridLayout,
+}
+
+impl Default for MapConfig {
+    fn default() -> Self {
+        MapConfig {
+            width: 100,
+            height: 100,
+            tile_size: 25.0,
+            origin: (0.0, 0.0),
+            layout: GridLayout::Square,
+        }
+    }
+}
+
+impl MapConfig {
+    /// the bottom left and top right tiles of the map
+    pub fn bounds(&self) -> (IVec2, IVec2) {
+        let min = -IVec2::new(self.width / 2, self.height / 2);
+        (min, min + IVec2::new(self.width - 1, self.height - 1))
+    }
+}
+
+#[derive(Component)]
+pub struct MapBorder;
+
+#[derive(Resource, Clone)]
 pub struct Grid {
-    pub tiles: HashMap<IVec2, TileState>,
+    tiles: ChunkedTiles<Tile>,
+    /// changes waiting to be sent as events
+    pending: Vec<TileChanged>,
+    /// goes up every time a tile starts or stops being walkable
+    walkability_generation: u64,
+    /// the grid entity covering each occupied tile
+    owners: ChunkedTiles<Option<Entity>>,
+    /// the bottom left and top right tiles, nothing outside them can be built on or walked over
+    bounds: Option<(IVec2, IVec2)>,
+    fog: ChunkedTiles<Fog>,
+    /// tiles whose fog changed since events were last sent
+    pending_fog: Vec<IVec2>,
+    pub layout: GridLayout,
     pub centre: Vec2,
+    /// the distance between the centres of neighbouring tiles
     pub tile_size: f32
 }
 
@@ -75,26 +265,269 @@ pub struct GridScale(pub Vec3);
 pub fn spawn_grid(
     mut commands: Commands
 ) {
-    let tile_size = 25.0;
-    commands.insert_resource(Grid{
-        tiles: HashMap::new(),
-        centre: Vec2::ZERO,
-        tile_size: tile_size
-    });
-    commands.insert_resource(GridScale(Vec3::splat(tile_size / SPRITE_TILE_SIZE)));
+    let config = fs::read_to_string(MAP_CONFIG_PATH).map_err(|e| e.to_string())
+        .and_then(|text| ron::from_str::<MapConfig>(&text).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            warn!("Failed to load {}, using the default map: {}", MAP_CONFIG_PATH, e);
+            MapConfig::default()
+        });
+    let (min, max) = config.bounds();
+    commands.insert_resource(Grid::new(Vec2::new(config.origin.0, config.origin.1), config.tile_size)
+        .with_bounds(min, max)
+        .with_layout(config.layout));
+    commands.insert_resource(GridScale(Vec3::splat(config.tile_size / SPRITE_TILE_SIZE)));
+}
+
+/// outlines the map, again whenever its bounds change
+pub fn draw_map_border(
+    mut commands: Commands,
+    grid: Res<Grid>,
+    border_query: Query<Entity, With<MapBorder>>,
+    mut drawn: Local<(Option<(IVec2, IVec2)>, GridLayout)>
+) {
+    if !grid.is_changed() || *drawn == (grid.bounds, grid.layout) {return;}
+    *drawn = (grid.bounds, grid.layout);
+    for entity in border_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    let Some((min, max)) = grid.bounds else {return};
+    if grid.layout == GridLayout::Hex {
+        draw_hex_border(&mut commands, &grid, min, max);
+        return;
+    }
+
+    let thickness = grid.tile_size * 0.2;
+    let bottom_left = grid_to_space(min, &grid) - Vec2::splat(grid.tile_size / 2.0);
+    let top_right = grid_to_space(max, &grid) + Vec2::splat(grid.tile_size / 2.0);
+    let size = top_right - bottom_left;
+    let centre = (bottom_left + top_right) / 2.0;
+    let sides = [
+        (Vec2::new(centre.x, bottom_left.y), Vec2::new(size.x + thickness, thickness)),
+        (Vec2::new(centre.x, top_right.y), Vec2::new(size.x + thickness, thickness)),
+        (Vec2::new(bottom_left.x, centre.y), Vec2::new(thickness, size.y + thickness)),
+        (Vec2::new(top_right.x, centre.y), Vec2::new(thickness, size.y + thickness)),
+    ];
+    for (position, size) in sides {
+        commands.spawn(SpriteBundle {
+            sprite: Sprite {
+                color: BORDER_COLOUR,
+                custom_size: Some(size),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(position.extend(0.6)),
+            ..Default::default()
+        })
+        .insert(MapBorder);
+    }
 }
 
 impl Index<IVec2> for Grid {
-    type Output = TileState;
+    type Output = Tile;
 
     fn index(&self, index: IVec2) -> &Self::Output {
-        if let Some(state) = self.tiles.get(&index) {
-            return &state;
-        } else {return &TileState::Empty;}
+        &self.tiles[index]
     }
 }
 
+impl Grid {
+    pub fn new(centre: Vec2, tile_size: f32) -> Self {
+        Grid {
+            tiles: ChunkedTiles::new(),
+            pending: Vec::new(),
+            walkability_generation: 0,
+            owners: ChunkedTiles::new(),
+            bounds: None,
+            fog: ChunkedTiles::new(),
+            pending_fog: Vec::new(),
+            layout: GridLayout::Square,
+            centre,
+            tile_size
+        }
+    }
+
+    /// limits the grid to the tiles from `min` to `max`
+    pub fn with_bounds(mut self, min: IVec2, max: IVec2) -> Self {
+        self.bounds = Some((min, max));
+        self
+    }
+
+    pub fn with_layout(mut self, layout: GridLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn bounds(&self) -> Option<(IVec2, IVec2)> {
+        self.bounds
+    }
+
+    pub fn in_bounds(&self, pos: IVec2) -> bool {
+        self.bounds.is_none_or(|(min, max)| pos.cmpge(min).all() && pos.cmple(max).all())
+    }
+
+    /// whether something can be built on the tile, which needs it to be clear of objects and not used to reach a building
+    pub fn is_free(&self, pos: IVec2) -> bool {
+        let tile = self[pos];
+        self.in_bounds(pos) && tile.object.is_none() && !tile.is_interaction_point()
+    }
+
+    /// changes every layer of a tile, returning what was there before
+    pub fn set(&mut self, pos: IVec2, tile: Tile) -> Tile {
+        let old = self.tiles.insert(pos, tile);
+        if old != tile {self.pending.push(TileChanged {pos, old, new: tile});}
+        if old.is_walkable() != tile.is_walkable() {self.walkability_generation += 1;}
+        old
+    }
+
+    /// Goes up whenever walkability changes, robots and fog coming and going don't count.
+    /// Anything planned against the grid is out of date once this has moved on
+    pub fn walkability_generation(&self) -> u64 {
+        self.walkability_generation
+    }
+
+    /// whether any tile has started or stopped being walkable since `TileChanged` events were last sent
+    pub fn has_pending_walkability_changes(&self) -> bool {
+        self.pending.iter().any(|change| change.walkability_changed())
+    }
+
+    /// A copy of what pathfinding needs, leaving out fog, owners and pending changes
+    pub fn path_snapshot(&self) -> Grid {
+        Grid {
+            tiles: self.tiles.clone(),
+            pending: Vec::new(),
+            walkability_generation: self.walkability_generation,
+            owners: ChunkedTiles::new(),
+            bounds: self.bounds,
+            fog: ChunkedTiles::new(),
+            pending_fog: Vec::new(),
+            layout: self.layout,
+            centre: self.centre,
+            tile_size: self.tile_size
+        }
+    }
+
+    fn update(&mut self, pos: IVec2, change: impl FnOnce(&mut Tile)) {
+        let mut tile = self[pos];
+        change(&mut tile);
+        self.set(pos, tile);
+    }
+
+    pub fn set_floor(&mut self, pos: IVec2, floor: Floor) {
+        self.update(pos, |tile| tile.floor = floor);
+    }
+
+    pub fn set_object(&mut self, pos: IVec2, object: Option<Object>) {
+        self.update(pos, |tile| tile.object = object);
+    }
+
+    pub fn set_occupant(&mut self, pos: IVec2, occupant: Option<Entity>) {
+        self.update(pos, |tile| tile.occupant = occupant);
+    }
+
+    pub fn add_interaction_point(&mut self, pos: IVec2) {
+        self.update(pos, |tile| tile.interaction_points += 1);
+    }
+
+    pub fn remove_interaction_point(&mut self, pos: IVec2) {
+        self.update(pos, |tile| tile.interaction_points = tile.interaction_points.saturating_sub(1));
+    }
+
+    pub fn tiles(&self) -> &ChunkedTiles<Tile> {
+        &self.tiles
+    }
+
+    pub fn fog(&self, pos: IVec2) -> Fog {
+        self.fog[pos]
+    }
+
+    pub fn set_fog(&mut self, pos: IVec2, fog: Fog) -> Fog {
+        let old = self.fog.insert(pos, fog);
+        if old != fog {self.pending_fog.push(pos);}
+        old
+    }
+
+    /// whether a robot has ever seen the tile
+    pub fn is_explored(&self, pos: IVec2) -> bool {
+        self.fog[pos] != Fog::Unexplored
+    }
+
+    /// every tile that has been seen, visible or not
+    pub fn explored(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.fog.iter().filter(|(_, fog)| *fog != Fog::Unexplored).map(|(pos, _)| pos)
+    }
+
+    /// the grid entity covering a tile
+    pub fn owner(&self, pos: IVec2) -> Option<Entity> {
+        self.owners[pos]
+    }
+
+    pub fn set_owner(&mut self, cells: &[IVec2], owner: Option<Entity>) {
+        for cell in cells {
+            self.owners.insert(*cell, owner);
+        }
+    }
+
+    /// swaps in the tiles of another grid, recording a change for every tile that differs
+    pub fn replace_with(&mut self, other: Grid) {
+        let mut changed: Vec<IVec2> = self.tiles.iter().chain(other.tiles.iter()).map(|(pos, _)| pos).collect();
+        changed.sort_by_key(|pos| (pos.x, pos.y));
+        changed.dedup();
+
+        for pos in changed {
+            self.set(pos, other.tiles[pos]);
+        }
+        self.owners = other.owners;
+        // the bounds may have moved too
+        self.walkability_generation += 1;
+        self.bounds = other.bounds;
+        let mut fogged: Vec<IVec2> = self.fog.iter().chain(other.fog.iter()).map(|(pos, _)| pos).collect();
+        fogged.sort_by_key(|pos| (pos.x, pos.y));
+        fogged.dedup();
+        for pos in fogged {
+            self.set_fog(pos, other.fog[pos]);
+        }
+        self.layout = other.layout;
+        self.centre = other.centre;
+        self.tile_size = other.tile_size;
+    }
+
+    pub fn is_walkable(&self, location: IVec2) -> bool {
+        self.in_bounds(location) && self[location].is_walkable()
+    }
+}
+
+/// the edges of a hex map aren't straight, so it is outlined with a dot on every tile just off the map
+fn draw_hex_border(commands: &mut Commands, grid: &Grid, min: IVec2, max: IVec2) {
+    let outside = (min.x - 1..=max.x + 1).flat_map(|x| [IVec2::new(x, min.y - 1), IVec2::new(x, max.y + 1)])
+        .chain((min.y..=max.y).flat_map(|y| [IVec2::new(min.x - 1, y), IVec2::new(max.x + 1, y)]));
+    for cell in outside {
+        commands.spawn(SpriteBundle {
+            sprite: Sprite {
+                color: BORDER_COLOUR,
+                custom_size: Some(Vec2::splat(grid.tile_size * 0.3)),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(grid_to_space(cell, grid).extend(0.6)),
+            ..Default::default()
+        })
+        .insert(MapBorder);
+    }
+}
+
+pub fn flush_tile_changes(
+    mut grid: ResMut<Grid>,
+    mut events: EventWriter<TileChanged>,
+    mut fog_events: EventWriter<FogChanged>
+) {
+    if grid.pending.is_empty() && grid.pending_fog.is_empty() {return;}
+    // sending the changes is not itself a change to the grid
+    let grid = grid.bypass_change_detection();
+    events.send_batch(grid.pending.drain(..));
+    let fog = &grid.fog;
+    fog_events.send_batch(grid.pending_fog.drain(..).map(|pos| FogChanged {pos, fog: fog[pos]}));
+}
+
 pub fn grid_to_space(grid_location: IVec2, grid: &Grid) -> Vec2 {
+    if grid.layout == GridLayout::Hex {return hex_to_space(grid_location, grid);}
     let x_offset = grid_location.x as f32 * grid.tile_size;
     let y_offset = grid_location.y as f32 * grid.tile_size;
 
@@ -102,40 +535,84 @@ pub fn grid_to_space(grid_location: IVec2, grid: &Grid) -> Vec2 {
 }
 
 pub fn space_to_grid(space_location: Vec3, grid: &Grid) -> IVec2 {
+    if grid.layout == GridLayout::Hex {return space_to_hex(space_location, grid);}
     let x = ((space_location.x - grid.centre.x + grid.tile_size * 0.5) / grid.tile_size).floor() as i32;
     let y = ((space_location.y - grid.centre.y + grid.tile_size * 0.5) / grid.tile_size).floor() as i32;
 
     IVec2::new(x, y)
 }
 
+pub const HEX_ROW_HEIGHT: f32 = 0.866_025_4; // sqrt(3) / 2
+
+fn hex_to_space(hex: IVec2, grid: &Grid) -> Vec2 {
+    let x_offset = (hex.x as f32 + hex.y as f32 * 0.5) * grid.tile_size;
+    let y_offset = hex.y as f32 * HEX_ROW_HEIGHT * grid.tile_size;
+
+    grid.centre + Vec2::new(x_offset, y_offset)
+}
+
+fn space_to_hex(space_location: Vec3, grid: &Grid) -> IVec2 {
+    let r = (space_location.y - grid.centre.y) / (HEX_ROW_HEIGHT * grid.tile_size);
+    let q = (space_location.x - grid.centre.x) / grid.tile_size - r * 0.5;
+
+    // round in cube coordinates, then fix whichever was rounded furthest so they still add up to zero
+    let s = -q - r;
+    let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+    let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+    if dq > dr && dq > ds {
+        rq = -rr - rs;
+    } else if dr > ds {
+        rr = -rq - rs;
+    }
+    IVec2::new(rq as i32, rr as i32)
+}
+
+/// deletes the grid entity covering a tile, returning it if there was one
 pub fn delete_grid_entity(
     commands: &mut Commands,
     grid: &mut Grid,
     location: IVec2,
     grid_entity_query: &Query<(&GridEntity, Entity)>
+) -> Option<Entity> {
+    let (grid_entity, entity) = grid.owner(location).and_then(|owner| grid_entity_query.get(owner).ok())?;
+
+    for cell in grid_entity.cells.iter() {
+        grid.set_object(*cell, None);
+    }
+    for cell in grid_entity.interaction_points.iter() {
+        grid.remove_interaction_point(*cell);
+    }
+    grid.set_owner(&grid_entity.cells, None);
+    commands.entity(entity).despawn_recursive();
+    Some(entity)
+}
+
+/// Panics if the owner index and the grid entities disagree
+#[cfg(debug_assertions)]
+pub fn check_owner_index(
+    grid: Res<Grid>,
+    grid_entity_query: Query<(Entity, &GridEntity)>
 ) {
-    for (grid_entity, entity) in grid_entity_query.iter() {
-        if grid_entity.contains_cell(location) {
+    if !grid.is_changed() {return;}
 
-            for cell in grid_entity.cells.iter() {
-                grid.tiles.insert(*cell, TileState::Empty);
-            }
-            commands.entity(entity).despawn_recursive();
+    let mut owned_cells = 0;
+    for (entity, grid_entity) in grid_entity_query.iter() {
+        for cell in grid_entity.cells.iter() {
+            assert_eq!(grid.owner(*cell), Some(entity), "{} is not owned by the grid entity {:?} covering it", cell, entity);
+            assert!(grid[*cell].object.is_some(), "{} is covered by {:?} but empty", cell, entity);
         }
+        owned_cells += grid_entity.cells.len();
     }
+    assert_eq!(grid.owners.iter().count(), owned_cells, "the owner index has tiles without a grid entity");
 }
 
 
 #[cfg(test)]
 mod space_to_grid_tests {
-    use super::{Grid, space_to_grid, Vec2, IVec2, Vec3, HashMap};
+    use super::{Grid, space_to_grid, Vec2, IVec2, Vec3};
     #[test]
     fn positive_tests() {
-        let grid = Grid {
-            tiles: HashMap::new(),
-            centre: Vec2::ZERO,
-            tile_size: 1.0
-        };
+        let grid = Grid::new(Vec2::ZERO, 1.0);
         let index = space_to_grid(Vec3::new(-0.25, -0.25, 0.0), &grid);
         assert_eq!(index, IVec2::new(0, 0));
         let index = space_to_grid(Vec3::new(1.0, 0.0, 0.0), &grid);
@@ -143,11 +620,7 @@ mod space_to_grid_tests {
         let index = space_to_grid(Vec3::new(1.0, 1.5, 0.0), &grid);
         assert_eq!(index, IVec2::new(1, 2));
 
-        let grid = Grid {
-            tiles: HashMap::new(),
-            centre: Vec2::ZERO,
-            tile_size: 0.5
-        };
+        let grid = Grid::new(Vec2::ZERO, 0.5);
         let index = space_to_grid(Vec3::new(-0.2, -0.2, 0.0), &grid);
         assert_eq!(index, IVec2::new(0, 0));
         let index = space_to_grid(Vec3::new(1.5, 0.5, 0.0), &grid);
@@ -156,11 +629,7 @@ mod space_to_grid_tests {
 
     #[test]
     fn negative_tests() {
-        let grid = Grid {
-            tiles: HashMap::new(),
-            centre: Vec2::ZERO,
-            tile_size: 1.0
-        };
+        let grid = Grid::new(Vec2::ZERO, 1.0);
         let index = space_to_grid(Vec3::new(-0.6, -0.5, 0.0), &grid);
         assert_eq!(index, IVec2::new(-1, 0));
         let index = space_to_grid(Vec3::new(-3.0, 2.0, 0.0), &grid);
@@ -168,14 +637,156 @@ mod space_to_grid_tests {
         let index = space_to_grid(Vec3::new(-1.0, -1.5, 0.0), &grid);
         assert_eq!(index, IVec2::new(-1, -1));
 
-        let grid = Grid {
-            tiles: HashMap::new(),
-            centre: Vec2::ZERO,
-            tile_size: 0.5
-        };
+        let grid = Grid::new(Vec2::ZERO, 0.5);
         let index = space_to_grid(Vec3::new(-1.2, -1.2, 0.0), &grid);
         assert_eq!(index, IVec2::new(-2, -2));
         let index = space_to_grid(Vec3::new(-1.5, -0.25, 0.0), &grid);
         assert_eq!(index, IVec2::new(-3, 0));
     }
 }
+
+#[cfg(test)]
+mod hex_tests {
+    use super::{Grid, GridLayout, grid_to_space, space_to_grid, Vec2, IVec2, Vec3};
+    #[test]
+    fn conversions() {
+        let grid = Grid::new(Vec2::ZERO, 1.0).with_layout(GridLayout::Hex);
+        assert_eq!(space_to_grid(Vec3::new(0.3, 0.2, 0.0), &grid), IVec2::new(0, 0));
+        assert_eq!(space_to_grid(Vec3::new(1.0, 0.0, 0.0), &grid), IVec2::new(1, 0));
+        assert_eq!(space_to_grid(Vec3::new(0.5, 0.87, 0.0), &grid), IVec2::new(0, 1));
+        assert_eq!(space_to_grid(Vec3::new(-0.5, -0.87, 0.0), &grid), IVec2::new(0, -1));
+        assert_eq!(space_to_grid(Vec3::new(-1.1, 0.1, 0.0), &grid), IVec2::new(-1, 0));
+
+        for q in -5..5 {
+            for r in -5..5 {
+                let hex = IVec2::new(q, r);
+                assert_eq!(space_to_grid(grid_to_space(hex, &grid).extend(0.0), &grid), hex);
+            }
+        }
+    }
+
+    #[test]
+    fn neighbours_and_distance() {
+        let layout = GridLayout::Hex;
+        assert_eq!(layout.neighbours().len(), 6);
+        for offset in layout.neighbours() {
+            assert_eq!(layout.distance(IVec2::ZERO, *offset), 1);
+        }
+        assert_eq!(layout.distance(IVec2::ZERO, IVec2::new(2, -1)), 2);
+        assert_eq!(layout.distance(IVec2::ZERO, IVec2::new(2, 2)), 4);
+        assert_eq!(GridLayout::Square.distance(IVec2::ZERO, IVec2::new(2, -1)), 3);
+    }
+}
+
+#[cfg(test)]
+mod tile_change_tests {
+    use super::{Floor, Fog, Grid, Object, Tile, TileChanged, Vec2, IVec2};
+    use bevy::prelude::Entity;
+
+    #[test]
+    fn changes_are_recorded() {
+        let wall = Tile {object: Some(Object::Wall), ..Default::default()};
+        let mut grid = Grid::new(Vec2::ZERO, 1.0);
+        grid.set_object(IVec2::new(1, 1), Some(Object::Wall));
+        // setting a tile to what it already is isn't a change
+        grid.set_object(IVec2::new(1, 1), Some(Object::Wall));
+        grid.set_object(IVec2::new(2, 1), None);
+        assert_eq!(grid.pending, vec![TileChanged {pos: IVec2::new(1, 1), old: Tile::default(), new: wall}]);
+        assert!(grid.pending[0].walkability_changed());
+        assert!(grid.has_pending_walkability_changes() && grid.walkability_generation() == 1);
+
+        grid.pending.clear();
+        let mut other = Grid::new(Vec2::ZERO, 1.0);
+        other.set(IVec2::new(1, 1), wall);
+        other.set_object(IVec2::new(0, 5), Some(Object::Building));
+        grid.replace_with(other);
+        assert_eq!(grid.pending, vec![TileChanged {
+            pos: IVec2::new(0, 5),
+            old: Tile::default(),
+            new: Tile {object: Some(Object::Building), ..Default::default()}
+        }]);
+    }
+
+    #[test]
+    fn fog_changes_are_recorded() {
+        let mut grid = Grid::new(Vec2::ZERO, 1.0);
+        grid.set_fog(IVec2::new(2, 2), Fog::Visible);
+        grid.set_fog(IVec2::new(2, 2), Fog::Visible);
+        grid.set_fog(IVec2::new(3, 2), Fog::Unexplored);
+        assert_eq!(grid.pending_fog, vec![IVec2::new(2, 2)]);
+
+        // loading another grid's fog redraws only the tiles that differ
+        grid.pending_fog.clear();
+        let mut other = Grid::new(Vec2::ZERO, 1.0);
+        other.set_fog(IVec2::new(2, 2), Fog::Visible);
+        other.set_fog(IVec2::new(-40, 7), Fog::Explored);
+        grid.replace_with(other);
+        assert_eq!(grid.pending_fog, vec![IVec2::new(-40, 7)]);
+        assert_eq!(grid.fog(IVec2::new(-40, 7)), Fog::Explored);
+    }
+
+    #[test]
+    fn layers() {
+        let mut grid = Grid::new(Vec2::ZERO, 1.0);
+        let pos = IVec2::new(3, 0);
+        grid.add_interaction_point(pos);
+        grid.add_interaction_point(pos);
+        grid.set_occupant(pos, Some(Entity::from_raw(1)));
+        // a robot standing on an interaction point leaves it there
+        grid.set_occupant(pos, None);
+        assert!(grid[pos].is_interaction_point() && grid.is_walkable(pos) && !grid.is_free(pos));
+        // none of that changes where robots can go
+        assert!(grid.walkability_generation() == 0 && !grid.has_pending_walkability_changes());
+
+        // shared by two buildings, so it stays until both are gone
+        grid.remove_interaction_point(pos);
+        assert!(grid[pos].is_interaction_point());
+        grid.remove_interaction_point(pos);
+        assert!(grid.is_free(pos));
+
+        // whatever is built on top, the floor underneath is kept
+        grid.set_floor(pos, Floor::OreDeposit);
+        grid.set_object(pos, Some(Object::Building));
+        assert!(!grid.is_walkable(pos));
+        grid.set_object(pos, None);
+        assert_eq!(grid[pos], Tile {floor: Floor::OreDeposit, ..Default::default()});
+        assert!(grid.is_walkable(pos));
+    }
+}
+
+#[cfg(test)]
+mod owner_tests {
+    use super::{Grid, Vec2, IVec2};
+    use bevy::prelude::Entity;
+
+    #[test]
+    fn owners() {
+        let mut grid = Grid::new(Vec2::ZERO, 1.0);
+        let cells = [IVec2::new(0, 0), IVec2::new(1, 0), IVec2::new(-40, 7)];
+        grid.set_owner(&cells, Some(Entity::from_raw(3)));
+        assert_eq!(grid.owner(IVec2::new(-40, 7)), Some(Entity::from_raw(3)));
+        assert_eq!(grid.owner(IVec2::new(2, 0)), None);
+
+        grid.set_owner(&cells[..2], None);
+        assert_eq!(grid.owner(IVec2::new(0, 0)), None);
+        assert_eq!(grid.owners.chunk_count(), 1);
+    }
+}
+
+#[cfg(test)]
+mod bounds_tests {
+    use super::{Grid, MapConfig, Object, Vec2, IVec2};
+
+    #[test]
+    fn bounds() {
+        let config = MapConfig {width: 5, height: 4, ..Default::default()};
+        assert_eq!(config.bounds(), (IVec2::new(-2, -2), IVec2::new(2, 1)));
+
+        let (min, max) = config.bounds();
+        let mut grid = Grid::new(Vec2::ZERO, 1.0).with_bounds(min, max);
+        assert!(grid.is_free(IVec2::new(2, 1)) && grid.is_walkable(IVec2::new(-2, -2)));
+        assert!(!grid.is_free(IVec2::new(3, 0)) && !grid.is_walkable(IVec2::new(0, 2)));
+        grid.set_object(IVec2::ZERO, Some(Object::Wall));
+        assert!(!grid.is_free(IVec2::ZERO));
+    }
+}
@@ -0,0 +1,243 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use std::collections::VecDeque;
+
+use crate::{
+    asset_loading::{BuildingAtlasHandle, BuildingBindings, DoorAtlasHandle, WallAtlasHandles},
+    building::{spawn_building, Building, Rotation},
+    doors::{spawn_door, Door},
+    grid::{delete_grid_entity, Grid, GridEntity, GridScale},
+    walls::{spawn_wall, WallKind, WallTag, WallTypes},
+    AppState
+};
+
+/// how many undo steps are kept before the oldest are forgotten
+pub const MAX_HISTORY: usize = 100;
+
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<History>()
+            .add_systems(Update, undo_redo.run_if(in_state(AppState::Finished)));
+    }
+}
+
+
+/// Something placed on the grid, found again by where it is as entities change on every respawn
+#[derive(Clone, Debug, PartialEq)]
+pub enum Placed {
+    /// the wall type's name and where it is, always put back at full hit points
+    Wall(String, IVec2),
+    Building(String, IVec2, Rotation),
+    /// where it is and whether it is locked or lets robots through
+    Door(IVec2, Door),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Place(Placed),
+    Delete(Placed),
+}
+
+impl Action {
+    pub fn inverse(&self) -> Action {
+        match self {
+            Action::Place(placed) => Action::Delete(placed.clone()),
+            Action::Delete(placed) => Action::Place(placed.clone()),
+        }
+    }
+}
+
+/// Undo and redo stacks of editing steps, where each step is every action of one mouse stroke
+#[derive(Resource, Default)]
+pub struct History {
+    undo: VecDeque<Vec<Action>>,
+    redo: Vec<Vec<Action>>,
+    /// the actions of the stroke in progress
+    stroke: Option<Vec<Action>>,
+}
+
+impl History {
+    pub fn begin_stroke(&mut self) {
+        self.end_stroke();
+        self.stroke = Some(Vec::new());
+    }
+
+    pub fn end_stroke(&mut self) {
+        if let Some(stroke) = self.stroke.take() {self.push(stroke);}
+    }
+
+    /// adds an action to the current stroke, or as a step of its own outside of one
+    pub fn record(&mut self, action: Action) {
+        match self.stroke.as_mut() {
+            Some(stroke) => stroke.push(action),
+            None => self.push(vec![action]),
+        }
+    }
+
+    fn push(&mut self, step: Vec<Action>) {
+        if step.is_empty() {return;}
+        self.redo.clear();
+        self.undo.push_back(step);
+        if self.undo.len() > MAX_HISTORY {self.undo.pop_front();}
+    }
+
+    /// the actions that undo the last step, in the order to perform them
+    pub fn undo(&mut self) -> Option<Vec<Action>> {
+        self.end_stroke();
+        let step = self.undo.pop_back()?;
+        let actions = step.iter().rev().map(Action::inverse).collect();
+        self.redo.push(step);
+        Some(actions)
+    }
+
+    pub fn redo(&mut self) -> Option<Vec<Action>> {
+        self.end_stroke();
+        let step = self.redo.pop()?;
+        self.undo.push_back(step.clone());
+        Some(step)
+    }
+
+    pub fn clear(&mut self) {
+        *self = History::default();
+    }
+}
+
+
+/// what tells apart the walls, buildings and doors found on the grid
+pub type PlacedParts = (&'static GridEntity, Option<&'static WallKind>, Option<&'static Building>, Option<&'static Door>);
+type IsPlaced = Or<(With<WallTag>, With<Building>, With<Door>)>;
+
+/// Everything needed to place and delete walls and buildings
+#[derive(SystemParam)]
+pub struct GridEditor<'w, 's> {
+    commands: Commands<'w, 's>,
+    grid: ResMut<'w, Grid>,
+    grid_scale: Res<'w, GridScale>,
+    wall_types: Res<'w, WallTypes>,
+    wall_atlases: Res<'w, WallAtlasHandles>,
+    building_atlas: Res<'w, BuildingAtlasHandle>,
+    door_atlas: Res<'w, DoorAtlasHandle>,
+    bindings: Res<'w, BuildingBindings>,
+    grid_entity_query: Query<'w, 's, (&'static GridEntity, Entity)>,
+    placed_query: Query<'w, 's, PlacedParts, IsPlaced>,
+}
+
+impl GridEditor<'_, '_> {
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    pub fn bindings(&self) -> &BuildingBindings {
+        &self.bindings
+    }
+
+    pub fn wall_types(&self) -> &WallTypes {
+        &self.wall_types
+    }
+
+    /// places the thing, returning whether there was room for it
+    pub fn place(&mut self, placed: &Placed) -> bool {
+        match placed {
+            // nothing can be built where no robot has been
+            Placed::Wall(name, location) => {
+                let Some(kind) = self.wall_types.by_name(name) else {return false};
+                if !self.grid.is_explored(*location) {return false;}
+                spawn_wall(&mut self.commands, &mut self.grid, &self.grid_scale, *location, kind, &self.wall_types, &self.wall_atlases).is_some()
+            },
+            Placed::Building(name, location, rotation) => {
+                let Some(spawn_info) = self.bindings.by_name(name) else {return false};
+                let size = rotation.rotate_size(spawn_info.max_offset);
+                let explored = (0..=size.x).all(|x| (0..=size.y).all(|y| self.grid.is_explored(*location + IVec2::new(x, y))));
+                if !explored {return false;}
+                spawn_building(&mut self.commands, &mut self.grid, &self.grid_scale, *location, *rotation, spawn_info, &self.building_atlas).is_some()
+            },
+            Placed::Door(location, door) => {
+                if !self.grid.is_explored(*location) {return false;}
+                spawn_door(&mut self.commands, &mut self.grid, &self.grid_scale, *location, *door, &self.door_atlas).is_some()
+            },
+        }
+    }
+
+    /// deletes whatever covers the tile, returning what was there
+    pub fn delete(&mut self, location: IVec2) -> Option<Placed> {
+        let entity = delete_grid_entity(&mut self.commands, &mut self.grid, location, &self.grid_entity_query)?;
+        let (grid_entity, kind, building, door) = self.placed_query.get(entity).ok()?;
+        Some(match (kind, building, door) {
+            (Some(kind), _, _) => Placed::Wall(self.wall_types.0[kind.0].name.clone(), grid_entity.min),
+            (None, Some(building), _) => Placed::Building(building.name.clone(), grid_entity.min, building.rotation),
+            (None, None, door) => Placed::Door(grid_entity.min, door.copied().unwrap_or_default()),
+        })
+    }
+
+    pub fn perform(&mut self, action: &Action) {
+        match action {
+            Action::Place(placed) => {self.place(placed);},
+            Action::Delete(Placed::Wall(_, location) | Placed::Building(_, location, _) | Placed::Door(location, _)) => {self.delete(*location);},
+        }
+    }
+}
+
+
+pub fn undo_redo(
+    keyboard: Res<Input<KeyCode>>,
+    mut history: ResMut<History>,
+    mut editor: GridEditor,
+) {
+    if !keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {return;}
+    let shift = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    let actions = if keyboard.just_pressed(KeyCode::Z) && !shift {
+        history.undo()
+    } else if keyboard.just_pressed(KeyCode::Y) || (keyboard.just_pressed(KeyCode::Z) && shift) {
+        history.redo()
+    } else {
+        return;
+    };
+    for action in actions.unwrap_or_default() {
+        editor.perform(&action);
+    }
+}
+
+
+#[cfg(test)]
+mod history_tests {
+    use super::{Action, History, Placed, MAX_HISTORY};
+    use bevy::prelude::*;
+
+    fn wall(x: i32) -> Action {
+        Action::Place(Placed::Wall("Stone".to_string(), IVec2::new(x, 0)))
+    }
+
+    #[test]
+    fn strokes_are_one_step() {
+        let mut history = History::default();
+        history.begin_stroke();
+        history.record(wall(0));
+        history.record(wall(1));
+        history.end_stroke();
+        history.record(wall(2));
+
+        assert_eq!(history.undo(), Some(vec![wall(2).inverse()]));
+        assert_eq!(history.undo(), Some(vec![wall(1).inverse(), wall(0).inverse()]));
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo(), Some(vec![wall(0), wall(1)]));
+
+        // doing something new forgets what could be redone
+        history.record(wall(3));
+        assert_eq!(history.redo(), None);
+    }
+
+    #[test]
+    fn bounded() {
+        let mut history = History::default();
+        for x in 0..MAX_HISTORY as i32 + 10 {
+            history.record(wall(x));
+        }
+        let mut steps = 0;
+        while history.undo().is_some() {steps += 1;}
+        assert_eq!(steps, MAX_HISTORY);
+    }
+}
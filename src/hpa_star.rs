@@ -0,0 +1,470 @@
+use bevy::{prelude::*, utils::HashMap};
+use std::{cmp::Reverse, collections::{BinaryHeap, VecDeque}, sync::{Arc, RwLock}};
+use crate::{grid::{Grid, GridLayout, TileChangeFlush, TileChanged, SQUARE_NEIGHBOURS}, pathfinding::{nearest_goal_distance, Pathfinder, SearchTrace}, AppState};
+
+const CLUSTER_SIZE: i32 = 10;
+const MAX_ABSTRACT_EXPANSIONS: usize = 20_000;
+/// border openings wider than this get an entrance at each end instead of one in the middle
+const MAX_ENTRANCE_WIDTH: usize = 6;
+
+
+pub struct HierarchicalPathPlugin;
+
+impl Plugin for HierarchicalPathPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(HierarchicalGraph::new(CLUSTER_SIZE))
+            .add_systems(PostUpdate, update_hierarchical_graph.after(TileChangeFlush).run_if(in_state(AppState::Finished)));
+    }
+}
+
+
+#[derive(Default, Clone)]
+struct Cluster {
+    /// entrance cell -> the cells across the cluster border it connects to
+    entrances: HashMap<IVec2, Vec<IVec2>>,
+    /// entrance cell -> (other entrance in this cluster, walking distance)
+    edges: HashMap<IVec2, Vec<(IVec2, u32)>>,
+}
+
+/// The clusters built so far, shared between every copy of a graph so searches on other threads add to them
+#[derive(Default)]
+struct SharedClusters {
+    clusters: HashMap<IVec2, Arc<Cluster>>,
+    /// the walkability generation each cluster was last thrown away at, see `Grid::walkability_generation`
+    invalidated_at: HashMap<IVec2, u64>,
+    /// the generation every cluster was last thrown away at
+    cleared_at: u64,
+}
+
+impl SharedClusters {
+    /// whether a cluster built from a grid of this generation is still right
+    fn is_current(&self, cluster: IVec2, generation: u64) -> bool {
+        self.cleared_at.max(self.invalidated_at.get(&cluster).copied().unwrap_or(0)) <= generation
+    }
+}
+
+/// Abstract graph for hierarchical pathfinding (HPA*).
+///
+/// The grid is split into square clusters which are built lazily the first time a search touches them,
+/// so the unbounded grid never has to be walked as a whole. Clusters are thrown away when a tile inside
+/// (or on the border of) them becomes walkable or stops being so, and rebuilt on the next search.
+///
+/// Copies share their clusters, so what a search on another thread builds is there for the next one.
+/// Each copy knows which generation of the grid it searches, and leaves alone any cluster that was
+/// thrown away after it
+#[derive(Resource, Clone)]
+pub struct HierarchicalGraph {
+    cluster_size: i32,
+    generation: u64,
+    shared: Arc<RwLock<SharedClusters>>,
+    /// the clusters the current search has used, so it sees the same ones throughout
+    local: HashMap<IVec2, Arc<Cluster>>,
+}
+
+impl HierarchicalGraph {
+    pub fn new(cluster_size: i32) -> Self {
+        HierarchicalGraph {
+            cluster_size,
+            generation: 0,
+            shared: Arc::default(),
+            local: HashMap::new(),
+        }
+    }
+
+    /// a copy for searching a snapshot of the grid taken at this walkability generation
+    pub fn at_generation(&self, generation: u64) -> Self {
+        HierarchicalGraph {generation, local: HashMap::new(), ..self.clone()}
+    }
+
+    /// a copy with clusters of its own, for searching a grid that may have changes the graph hasn't heard of
+    pub fn detached(&self) -> Self {
+        HierarchicalGraph::new(self.cluster_size)
+    }
+
+    fn cluster_of(&self, cell: IVec2) -> IVec2 {
+        IVec2::new(cell.x.div_euclid(self.cluster_size), cell.y.div_euclid(self.cluster_size))
+    }
+
+    fn cluster_bounds(&self, cluster: IVec2) -> (IVec2, IVec2) {
+        let min = cluster * self.cluster_size;
+        (min, min + IVec2::splat(self.cluster_size - 1))
+    }
+
+    /// drops every cluster whose entrances or edges could depend on the given cell
+    pub fn invalidate(&mut self, cell: IVec2) {
+        let cluster = self.cluster_of(cell);
+        let local = cell - cluster * self.cluster_size;
+        let mut affected = vec![cluster];
+        if local.x == 0 {affected.push(cluster - IVec2::X);}
+        if local.x == self.cluster_size - 1 {affected.push(cluster + IVec2::X);}
+        if local.y == 0 {affected.push(cluster - IVec2::Y);}
+        if local.y == self.cluster_size - 1 {affected.push(cluster + IVec2::Y);}
+
+        let mut shared = self.shared.write().unwrap();
+        for cluster in affected {
+            shared.clusters.remove(&cluster);
+            shared.invalidated_at.insert(cluster, self.generation);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        let mut shared = self.shared.write().unwrap();
+        shared.clusters.clear();
+        shared.invalidated_at.clear();
+        shared.cleared_at = self.generation;
+    }
+
+    /// the cluster as this search has seen it, as some other search built it or built from scratch, in that order
+    fn cluster(&mut self, cluster: IVec2, grid: &Grid) -> Arc<Cluster> {
+        if let Some(seen) = self.local.get(&cluster) {return seen.clone();}
+        let cached = {
+            let shared = self.shared.read().unwrap();
+            shared.clusters.get(&cluster).filter(|_| shared.is_current(cluster, self.generation)).cloned()
+        };
+        let found = cached.unwrap_or_else(|| {
+            let built = Arc::new(self.build_cluster(cluster, grid));
+            let mut shared = self.shared.write().unwrap();
+            // a search of an older grid mustn't put back a cluster that has been thrown away since
+            if shared.is_current(cluster, self.generation) {shared.clusters.insert(cluster, built.clone());}
+            built
+        });
+        self.local.insert(cluster, found.clone());
+        found
+    }
+
+    fn build_cluster(&self, cluster: IVec2, grid: &Grid) -> Cluster {
+        let (min, max) = self.cluster_bounds(cluster);
+        let mut result = Cluster::default();
+
+        for dir in SQUARE_NEIGHBOURS {
+            // walk the border on this side in ascending order, so the neighbouring cluster
+            // walking its opposite side finds exactly the same openings
+            let border: Vec<IVec2> = match (dir.x, dir.y) {
+                (1, 0) => (min.y..=max.y).map(|y| IVec2::new(max.x, y)).collect(),
+                (-1, 0) => (min.y..=max.y).map(|y| IVec2::new(min.x, y)).collect(),
+                (0, 1) => (min.x..=max.x).map(|x| IVec2::new(x, max.y)).collect(),
+                _ => (min.x..=max.x).map(|x| IVec2::new(x, min.y)).collect(),
+            };
+
+            let mut run = Vec::new();
+            for cell in border {
+                if grid.is_walkable(cell) && grid.is_walkable(cell + dir) {
+                    run.push(cell);
+                    continue;
+                }
+                add_entrances(&mut result, &run, dir);
+                run.clear();
+            }
+            add_entrances(&mut result, &run, dir);
+        }
+
+        let entrances: Vec<IVec2> = result.entrances.keys().copied().collect();
+        for entrance in entrances.iter() {
+            let field = flood_fill(*entrance, min, max, grid);
+            let links = entrances.iter()
+                .filter(|other| *other != entrance)
+                .filter_map(|other| field.get(other).map(|(dist, _)| (*other, *dist)))
+                .collect();
+            result.edges.insert(*entrance, links);
+        }
+
+        result
+    }
+
+    /// Finds a path to whichever of the goals is nearest by searching the abstract graph and refining each abstract step.
+    ///
+    /// Like `a_star`, the path is returned from the chosen goal back to start
+    pub fn search(&mut self, start: IVec2, goals: &[IVec2], grid: &Grid, mut trace: Option<&mut SearchTrace>) -> Option<Vec<IVec2>> {
+        let goals: Vec<IVec2> = goals.iter().copied().filter(|goal| grid.is_walkable(*goal)).collect();
+        if goals.is_empty() {return None;}
+        if goals.contains(&start) {return Some(vec![start]);}
+        self.local.clear();
+
+        let start_cluster = self.cluster_of(start);
+        let (start_min, start_max) = self.cluster_bounds(start_cluster);
+        let start_field = flood_fill(start, start_min, start_max, grid);
+        let start_links: Vec<(IVec2, u32)> = self.cluster(start_cluster, grid).entrances.keys()
+            .chain(goals.iter())
+            .filter_map(|cell| start_field.get(cell).map(|(dist, _)| (*cell, *dist)))
+            .collect();
+
+        // entrance -> (goal, distance) for the clusters the goals are in
+        let mut goal_links: HashMap<IVec2, Vec<(IVec2, u32)>> = HashMap::new();
+        for goal in goals.iter() {
+            let goal_cluster = self.cluster_of(*goal);
+            let (goal_min, goal_max) = self.cluster_bounds(goal_cluster);
+            let goal_field = flood_fill(*goal, goal_min, goal_max, grid);
+            for entrance in self.cluster(goal_cluster, grid).entrances.keys() {
+                if let Some((dist, _)) = goal_field.get(entrance) {
+                    goal_links.entry(*entrance).or_default().push((*goal, *dist));
+                }
+            }
+        }
+        let heuristic = |cell: IVec2| nearest_goal_distance(cell, &goals);
+
+        let mut open = BinaryHeap::new();
+        let mut g_scores: HashMap<IVec2, u32> = HashMap::new();
+        let mut came_from: HashMap<IVec2, IVec2> = HashMap::new();
+        g_scores.insert(start, 0);
+        open.push(Reverse((heuristic(start), 0, start.to_array())));
+
+        let mut expansions = 0;
+        let mut result = None;
+        while let Some(Reverse((_, g, pos))) = open.pop() {
+            let pos = IVec2::from_array(pos);
+            if goals.contains(&pos) {
+                result = Some(self.refine(&came_from, start, pos, grid));
+                break;
+            }
+            if g > g_scores[&pos] {continue;}
+            if let Some(trace) = trace.as_mut() {trace.explored.push(pos);}
+
+            expansions += 1;
+            if expansions > MAX_ABSTRACT_EXPANSIONS {break;}
+
+            let mut neighbours = Vec::new();
+            if pos == start {neighbours.extend(start_links.iter().copied());}
+
+            let cluster = self.cluster(self.cluster_of(pos), grid);
+            if let Some(edges) = cluster.edges.get(&pos) {
+                neighbours.extend(edges.iter().copied());
+            }
+            if let Some(twins) = cluster.entrances.get(&pos) {
+                neighbours.extend(twins.iter().map(|twin| (*twin, 1)));
+            }
+            if let Some(links) = goal_links.get(&pos) {neighbours.extend(links.iter().copied());}
+
+            for (next, cost) in neighbours {
+                let next_g = g + cost;
+                if next_g < *g_scores.get(&next).unwrap_or(&u32::MAX) {
+                    g_scores.insert(next, next_g);
+                    came_from.insert(next, pos);
+                    open.push(Reverse((next_g + heuristic(next), next_g, next.to_array())));
+                }
+            }
+        }
+
+        if let Some(trace) = trace {
+            trace.frontier = open.into_iter().map(|Reverse((_, _, cell))| IVec2::from_array(cell)).collect();
+        }
+        result
+    }
+
+    /// turns the chain of abstract nodes into a full cell path, searching inside one cluster per step
+    fn refine(&self, came_from: &HashMap<IVec2, IVec2>, start: IVec2, end: IVec2, grid: &Grid) -> Vec<IVec2> {
+        let mut path = vec![end];
+        let mut current = end;
+
+        while current != start {
+            let previous = came_from[&current];
+            if GridLayout::Square.distance(previous, current) == 1 {
+                path.push(previous);
+            } else {
+                let (min, max) = self.cluster_bounds(self.cluster_of(current));
+                let field = flood_fill(previous, min, max, grid);
+                let mut step = trace_field(&field, previous, current);
+                step.remove(0);
+                path.append(&mut step);
+            }
+            current = previous;
+        }
+
+        path
+    }
+}
+
+impl Pathfinder for HierarchicalGraph {
+    fn find_path_traced(&mut self, start: IVec2, goals: &[IVec2], grid: &Grid, trace: Option<&mut SearchTrace>) -> Option<Vec<IVec2>> {
+        self.search(start, goals, grid, trace)
+    }
+}
+
+
+fn add_entrances(cluster: &mut Cluster, run: &[IVec2], dir: IVec2) {
+    if run.is_empty() {return;}
+
+    let picks = if run.len() > MAX_ENTRANCE_WIDTH {
+        vec![run[0], run[run.len() - 1]]
+    } else {
+        vec![run[run.len() / 2]]
+    };
+
+    for cell in picks {
+        cluster.entrances.entry(cell).or_default().push(cell + dir);
+    }
+}
+
+/// breadth first search from `from` that never leaves the given bounds,
+/// returns (distance, parent) for every cell reached
+fn flood_fill(from: IVec2, min: IVec2, max: IVec2, grid: &Grid) -> HashMap<IVec2, (u32, IVec2)> {
+    let mut visited = HashMap::new();
+    let mut queue = VecDeque::new();
+    visited.insert(from, (0, from));
+    queue.push_back(from);
+
+    while let Some(current) = queue.pop_front() {
+        let dist = visited[&current].0;
+        for dir in SQUARE_NEIGHBOURS {
+            let next = current + dir;
+            if next.cmplt(min).any() || next.cmpgt(max).any() {continue;}
+            if visited.contains_key(&next) || !grid.is_walkable(next) {continue;}
+            visited.insert(next, (dist + 1, current));
+            queue.push_back(next);
+        }
+    }
+
+    visited
+}
+
+/// walks the parents in a flood fill field from `to` back to the field's origin `from`
+fn trace_field(field: &HashMap<IVec2, (u32, IVec2)>, from: IVec2, to: IVec2) -> Vec<IVec2> {
+    let mut path = vec![to];
+    let mut current = to;
+    while current != from {
+        current = field[&current].1;
+        path.push(current);
+    }
+    path
+}
+
+
+pub fn update_hierarchical_graph(
+    mut graph: ResMut<HierarchicalGraph>,
+    mut tile_changes: EventReader<TileChanged>,
+    grid: Res<Grid>,
+    mut bounds: Local<Option<(IVec2, IVec2)>>,
+) {
+    if graph.generation != grid.walkability_generation() {graph.generation = grid.walkability_generation();}
+    // a save with other bounds was loaded, so everything past the old edges changed at once
+    if *bounds != grid.bounds() {
+        *bounds = grid.bounds();
+        graph.clear();
+    }
+    for change in tile_changes.read() {
+        if change.walkability_changed() {graph.invalidate(change.pos);}
+    }
+}
+
+
+#[cfg(test)]
+mod hpa_tests {
+    use super::{HierarchicalGraph, IVec2};
+    use crate::{grid::{Grid, GridLayout, Object}, pathfinding::Pathfinder};
+
+    fn empty_grid() -> Grid {
+        Grid::new(bevy::math::Vec2::ZERO, 1.0)
+    }
+
+    fn assert_connected(path: &[IVec2], start: IVec2, end: IVec2, grid: &Grid) {
+        assert_eq!(path[0], end);
+        assert_eq!(*path.last().unwrap(), start);
+        for pair in path.windows(2) {
+            assert_eq!(GridLayout::Square.distance(pair[0], pair[1]), 1);
+            assert!(grid.is_walkable(pair[0]));
+        }
+    }
+
+    #[test]
+    fn open_ground() {
+        let grid = empty_grid();
+        let mut graph = HierarchicalGraph::new(10);
+
+        let path = graph.find_path(IVec2::new(-3, 2), IVec2::new(45, -17), &grid).unwrap();
+        assert_connected(&path, IVec2::new(-3, 2), IVec2::new(45, -17), &grid);
+        assert_eq!(path.len(), 48 + 19 + 1);
+
+        let path = graph.find_path(IVec2::new(1, 1), IVec2::new(4, 1), &grid).unwrap();
+        assert_eq!(path.len(), 4);
+    }
+
+    #[test]
+    fn through_gap() {
+        let mut grid = empty_grid();
+        for y in -50..=50 {
+            if y != 37 {grid.set_object(IVec2::new(15, y), Some(Object::Wall));}
+        }
+        let mut graph = HierarchicalGraph::new(10);
+
+        let path = graph.find_path(IVec2::new(0, 0), IVec2::new(30, 0), &grid).unwrap();
+        assert_connected(&path, IVec2::new(0, 0), IVec2::new(30, 0), &grid);
+        assert!(path.contains(&IVec2::new(15, 37)));
+    }
+
+    #[test]
+    fn invalidation() {
+        let mut grid = empty_grid();
+        let mut graph = HierarchicalGraph::new(10);
+        assert!(graph.find_path(IVec2::new(0, 0), IVec2::new(25, 5), &grid).is_some());
+
+        // box in the goal, the cached clusters still think it is open until invalidated
+        for cell in [IVec2::new(24, 5), IVec2::new(26, 5), IVec2::new(25, 4), IVec2::new(25, 6)] {
+            grid.set_object(cell, Some(Object::Wall));
+            graph.invalidate(cell);
+        }
+        assert!(graph.find_path(IVec2::new(0, 0), IVec2::new(25, 5), &grid).is_none());
+
+        grid.set_object(IVec2::new(25, 6), None);
+        graph.invalidate(IVec2::new(25, 6));
+        let path = graph.find_path(IVec2::new(0, 0), IVec2::new(25, 5), &grid).unwrap();
+        assert_eq!(path[1], IVec2::new(25, 6));
+    }
+
+    #[test]
+    fn copies_share_clusters() {
+        let mut grid = empty_grid();
+        let mut graph = HierarchicalGraph::new(10);
+        let (start, end, walled) = (IVec2::new(0, 0), IVec2::new(25, 5), IVec2::new(2, 0));
+        graph.at_generation(0).find_path(start, end, &grid).unwrap();
+        assert!(graph.shared.read().unwrap().clusters.contains_key(&walled));
+
+        // a copy still searching the grid from before the wall went up can't put its clusters back
+        let mut old = graph.at_generation(grid.walkability_generation());
+        let old_grid = grid.clone();
+        grid.set_object(IVec2::new(24, 5), Some(Object::Wall));
+        graph.generation = grid.walkability_generation();
+        graph.invalidate(IVec2::new(24, 5));
+        old.find_path(start, end, &old_grid).unwrap();
+        assert!(!graph.shared.read().unwrap().clusters.contains_key(&walled));
+
+        graph.at_generation(grid.walkability_generation()).find_path(start, end, &grid).unwrap();
+        assert!(graph.shared.read().unwrap().clusters.contains_key(&walled));
+    }
+
+    #[test]
+    fn nearest_goal() {
+        let mut grid = empty_grid();
+        for y in -20..=20 {
+            grid.set_object(IVec2::new(12, y), Some(Object::Wall));
+        }
+        let mut graph = HierarchicalGraph::new(10);
+
+        // the closer goal as the crow flies is behind the wall
+        let goals = [IVec2::new(13, 0), IVec2::new(-9, 0), IVec2::new(0, 40)];
+        let path = graph.find_path_to_any(IVec2::new(0, 0), &goals, &grid).unwrap();
+        assert_connected(&path, IVec2::new(0, 0), IVec2::new(-9, 0), &grid);
+        assert_eq!(path.len(), 10);
+    }
+
+    #[test]
+    fn large_map() {
+        // serpentine walls across a 500x500 area, each with a single gap at alternating ends
+        let mut grid = empty_grid();
+        for i in -1..=500 {
+            for cell in [IVec2::new(i, -1), IVec2::new(i, 500), IVec2::new(-1, i), IVec2::new(500, i)] {
+                grid.set_object(cell, Some(Object::Wall));
+            }
+        }
+        for x in (5..500).step_by(10) {
+            let gap = if (x / 10) % 2 == 0 {499} else {0};
+            for y in 0..500 {
+                if y != gap {grid.set_object(IVec2::new(x, y), Some(Object::Wall));}
+            }
+        }
+        let mut graph = HierarchicalGraph::new(10);
+
+        let path = graph.find_path(IVec2::new(0, 0), IVec2::new(499, 499), &grid).unwrap();
+        assert_connected(&path, IVec2::new(0, 0), IVec2::new(499, 499), &grid);
+        assert!(path.len() > 50 * 499);
+    }
+}
@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 
-use crate::{AppState, grid::{Grid, grid_to_space, space_to_grid, GridEntity, delete_grid_entity, GridScale}, walls::spawn_wall, asset_loading::{WallAtlasHandle, StepableAnimation, SelectionSpriteAtlasHandle, BuildingAtlasHandle, BuildingBindings}, building::spawn_building};
+use crate::{AppState, grid::{Grid, grid_to_space, space_to_grid, GridScale}, building::{Building, Rotation}, doors::Door, walls::{HitPoints, WallKind, WallTypes}, asset_loading::{StepableAnimation, SelectionSpriteAtlasHandle, BuildingBindings}, history::{Action, GridEditor, History, Placed}, blueprint::BlueprintTool};
 
 
 pub struct TileSelectPlugin;
@@ -62,8 +62,9 @@ pub struct InteractionPlugin;
 impl Plugin for InteractionPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_systems(OnEnter(AppState::Finished), spawn_interaction_selection)
-            .add_systems(Update, interaction.run_if(in_state(AppState::Finished)));
+            .add_systems(OnEnter(AppState::Finished), (spawn_interaction_selection, spawn_hover_info))
+            .init_resource::<PlacementRotation>()
+            .add_systems(Update, (rotate_placement.after(update_interaction_selection), interaction, update_hover_info).run_if(in_state(AppState::Finished)));
     }
 }
 
@@ -123,7 +124,7 @@ pub fn spawn_interaction_selection(
         .insert(StepableAnimation {
             current_index: 0,
             first: 0,
-            len: 6
+            len: indices.count
         });
     });
 }
@@ -142,45 +143,132 @@ pub fn update_interaction_selection(
     image.index = animation_index.current_index;
 }
 
+/// How new buildings are turned, changed with R
+#[derive(Resource, Default)]
+pub struct PlacementRotation(pub Rotation);
+
+pub fn rotate_placement(
+    keyboard: Res<Input<KeyCode>>,
+    building_bindings: Res<BuildingBindings>,
+    mut rotation: ResMut<PlacementRotation>,
+    mut selection_query: Query<(&StepableAnimation, &mut Transform)>
+) {
+    if keyboard.just_pressed(KeyCode::R) {rotation.0 = rotation.0.next();}
+    // turn the selected building's icon to match, anything else is shown upright
+    for (selection, mut transform) in selection_query.iter_mut() {
+        let angle = if building_bindings.0.contains_key(&selection.current_index) {rotation.0.angle()} else {0.0};
+        let turned = Quat::from_rotation_z(angle);
+        if transform.rotation != turned {transform.rotation = turned;}
+    }
+}
+
+/// Text in the corner saying what is under the cursor
+#[derive(Component)]
+pub struct HoverInfo;
+
+pub fn spawn_hover_info(
+    mut commands: Commands
+) {
+    commands.spawn(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .insert(HoverInfo);
+}
+
+pub fn update_hover_info(
+    tile_select: Query<&TileSelectIndicator>,
+    mut text_query: Query<&mut Text, With<HoverInfo>>,
+    building_query: Query<&Building>,
+    wall_query: Query<(&WallKind, &HitPoints)>,
+    door_query: Query<&Door>,
+    wall_types: Res<WallTypes>,
+    grid: Res<Grid>
+) {
+    let pos = tile_select.single().pos;
+    let tile = grid[pos];
+    let mut layers = vec![match grid.owner(pos) {
+        _ if !grid.in_bounds(pos) => "Outside the map".to_string(),
+        _ if !grid.is_explored(pos) => "Unexplored".to_string(),
+        Some(owner) if wall_query.contains(owner) => {
+            let (kind, hit_points) = wall_query.get(owner).unwrap();
+            format!("{} wall ({} hp)", wall_types.0[kind.0].name, hit_points.0)
+        },
+        Some(owner) => match door_query.get(owner) {
+            Ok(door) => door.describe().to_string(),
+            Err(_) => building_query.get(owner).map_or("Unknown".to_string(), |building| building.name.clone()),
+        },
+        None => tile.object.map_or(format!("{:?}", tile.floor), |object| format!("{:?} on {:?}", object, tile.floor)),
+    }];
+    if grid.is_explored(pos) {
+        if tile.is_interaction_point() {layers.push("interaction point".to_string());}
+        if tile.occupant.is_some() {layers.push("robot".to_string());}
+    }
+    let info = format!("({}, {}) {}", pos.x, pos.y, layers.join(", "));
+
+    let mut text = text_query.single_mut();
+    if text.sections.first().map(|section| &section.value) != Some(&info) {
+        *text = Text::from_section(info, TextStyle::default());
+    }
+}
+
 #[derive(Resource, Debug)]
 pub struct InteractionSpriteIndices {
     pub delete: usize,
-    pub wall: usize,
+    /// one for each of the `WallTypes`, in the same order
+    pub walls: Vec<usize>,
+    pub door: usize,
+    /// how many things there are to pick from
+    pub count: usize,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn interaction(
     mouse: Res<Input<MouseButton>>,
+    keyboard: Res<Input<KeyCode>>,
     tile_select: Query<&TileSelectIndicator>,
     info_index: Query<&StepableAnimation>,
     interaction_indices: Res<InteractionSpriteIndices>,
     building_bindings: Res<BuildingBindings>,
-
-    mut commands: Commands,
-    mut grid: ResMut<Grid>,
-    grid_scale: Res<GridScale>,
-    wall_atlas: Res<WallAtlasHandle>,
-    grid_entity_query: Query<(&GridEntity, Entity)>,
-    building_atlas: Res<BuildingAtlasHandle>,
-
-    
+    rotation: Res<PlacementRotation>,
+    blueprint_tool: Res<BlueprintTool>,
+    mut history: ResMut<History>,
+    mut editor: GridEditor,
+    mut placing: Local<bool>,
 ) {
     let tile_pos = tile_select.single();
     let index = info_index.single();
 
-    if mouse.pressed(MouseButton::Left) {
-        if index.current_index == interaction_indices.delete {
-            delete_grid_entity(&mut commands, &mut grid, tile_pos.pos, &grid_entity_query);
-        } else if index.current_index == interaction_indices.wall {
-            spawn_wall(&mut commands, &mut grid, &grid_scale, tile_pos.pos, &wall_atlas);
-        } else {
-            if let Some(spawn_info) = building_bindings.0.get(&index.current_index) {
-                // println!("{}", index.current_index);
-                // println!("{}", spawn_info.1);
-
-                spawn_building(&mut commands, &mut grid, &grid_scale, tile_pos.pos, spawn_info, &building_atlas);
-            }
-        }
+    // strokes that start on a blueprint action belong to it until the button is let go
+    if mouse.just_pressed(MouseButton::Left) {
+        *placing = !blueprint_tool.is_busy(&keyboard);
+        // everything done while the button is held is undone together
+        if *placing {history.begin_stroke();}
+    }
+    if mouse.just_released(MouseButton::Left) && *placing {history.end_stroke();}
+    if !*placing || !mouse.pressed(MouseButton::Left) || keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {return;}
 
+    if index.current_index == interaction_indices.delete {
+        if let Some(placed) = editor.delete(tile_pos.pos) {
+            history.record(Action::Delete(placed));
+        }
+        return;
+    }
 
+    // walls are drawn as lines and rectangles instead, see `wall_drawing`
+    let placed = if index.current_index == interaction_indices.door {
+        Placed::Door(tile_pos.pos, Door::default())
+    } else if let Some(spawn_info) = building_bindings.0.get(&index.current_index) {
+        Placed::Building(spawn_info.name.clone(), tile_pos.pos, rotation.0)
+    } else {
+        return;
+    };
+    if editor.place(&placed) {
+        history.record(Action::Place(placed));
     }
-}
\ No newline at end of file
+}
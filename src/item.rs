@@ -1,7 +1,25 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
 
-
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Item {
     Ore,
     Bar,
     Gear
-}
\ No newline at end of file
+}
+
+/// Items a robot is carrying, or a building is holding such as the ore a miner has dug up
+#[derive(Component, Default, Clone, Debug, PartialEq)]
+pub struct Inventory {
+    pub items: HashMap<Item, u32>
+}
+
+impl Inventory {
+    pub fn add(&mut self, item: Item, amount: u32) {
+        *self.items.entry(item).or_insert(0) += amount;
+    }
+
+    pub fn count(&self, item: Item) -> u32 {
+        self.items.get(&item).copied().unwrap_or(0)
+    }
+}
@@ -0,0 +1,187 @@
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+use std::{cmp::Reverse, collections::BinaryHeap};
+use crate::{grid::{Grid, GridLayout, SQUARE_NEIGHBOURS}, pathfinding::{nearest_goal_distance, Pathfinder, SearchTrace}};
+
+/// jumps stop after this many tiles and carry on from a fully expanded node, as open ground never ends
+const MAX_JUMP: i32 = 64;
+const MAX_EXPANSIONS: usize = 100_000;
+
+
+pub struct JumpPointSearch;
+
+impl Pathfinder for JumpPointSearch {
+    fn find_path_traced(&mut self, start: IVec2, goals: &[IVec2], grid: &Grid, trace: Option<&mut SearchTrace>) -> Option<Vec<IVec2>> {
+        jump_point_search(start, goals, grid, trace)
+    }
+}
+
+
+/// Jump point search for four connected grids.
+///
+/// Paths are kept canonical by always moving vertically before horizontally, so a horizontal jump only
+/// has to stop where the tile behind it above or below is blocked, and a vertical jump stops wherever a
+/// horizontal scan from it would find something.
+pub fn jump_point_search(start: IVec2, goals: &[IVec2], grid: &Grid, mut trace: Option<&mut SearchTrace>) -> Option<Vec<IVec2>> {
+    let goals: Vec<IVec2> = goals.iter().copied().filter(|goal| grid.is_walkable(*goal)).collect();
+    if goals.is_empty() {return None;}
+    if goals.contains(&start) {return Some(vec![start]);}
+
+    let mut open = BinaryHeap::new();
+    let mut closed = HashSet::new();
+    let mut g_scores: HashMap<IVec2, u32> = HashMap::new();
+    let mut came_from: HashMap<IVec2, IVec2> = HashMap::new();
+    g_scores.insert(start, 0);
+    // the last element is the direction the node was reached in, 4 for nodes that expand in every direction
+    open.push(Reverse((nearest_goal_distance(start, &goals), Reverse(0), start.to_array(), 4)));
+
+    let mut expansions = 0;
+    let mut result = None;
+    while let Some(Reverse((_, Reverse(g), pos, dir_index))) = open.pop() {
+        let pos = IVec2::from_array(pos);
+        if goals.contains(&pos) {
+            result = Some(fill_path(&came_from, start, pos));
+            break;
+        }
+        if g > g_scores[&pos] || !closed.insert((pos, dir_index)) {continue;}
+        if let Some(trace) = trace.as_mut() {trace.explored.push(pos);}
+
+        expansions += 1;
+        if expansions > MAX_EXPANSIONS {break;}
+
+        for dir in successor_directions(pos, dir_index, grid) {
+            let Some((jump_point, capped)) = jump(pos, dir, &goals, grid) else {continue};
+            let next_g = g + GridLayout::Square.distance(pos, jump_point);
+            if next_g <= *g_scores.get(&jump_point).unwrap_or(&u32::MAX) {
+                g_scores.insert(jump_point, next_g);
+                came_from.insert(jump_point, pos);
+                let next_dir = if capped {4} else {SQUARE_NEIGHBOURS.iter().position(|d| *d == dir).unwrap()};
+                let f = next_g + nearest_goal_distance(jump_point, &goals);
+                open.push(Reverse((f, Reverse(next_g), jump_point.to_array(), next_dir)));
+            }
+        }
+    }
+
+    if let Some(trace) = trace {
+        trace.frontier = open.into_iter().map(|Reverse((_, _, cell, _))| IVec2::from_array(cell)).collect();
+    }
+    result
+}
+
+fn successor_directions(pos: IVec2, dir_index: usize, grid: &Grid) -> Vec<IVec2> {
+    let Some(dir) = SQUARE_NEIGHBOURS.get(dir_index).copied() else {
+        return SQUARE_NEIGHBOURS.to_vec();
+    };
+
+    if dir.y != 0 {
+        return vec![dir, IVec2::X, IVec2::NEG_X];
+    }
+
+    let mut result = vec![dir];
+    for side in [IVec2::Y, IVec2::NEG_Y] {
+        if grid.is_walkable(pos + side) && !grid.is_walkable(pos - dir + side) {result.push(side);}
+    }
+    result
+}
+
+/// Steps from `pos` in `dir` until something interesting is found. The flag is set when the jump
+/// stopped because it got too long rather than because of a jump point.
+fn jump(pos: IVec2, dir: IVec2, goals: &[IVec2], grid: &Grid) -> Option<(IVec2, bool)> {
+    let mut current = pos;
+
+    for _ in 0..MAX_JUMP {
+        current += dir;
+        if !grid.is_walkable(current) {return None;}
+        if goals.contains(&current) {return Some((current, false));}
+
+        if dir.y == 0 {
+            if has_forced_neighbour(current, dir, grid) {return Some((current, false));}
+        } else if scan_horizontal(current, IVec2::X, goals, grid) || scan_horizontal(current, IVec2::NEG_X, goals, grid) {
+            return Some((current, false));
+        }
+    }
+
+    Some((current, true))
+}
+
+fn has_forced_neighbour(pos: IVec2, dir: IVec2, grid: &Grid) -> bool {
+    [IVec2::Y, IVec2::NEG_Y].iter()
+        .any(|side| grid.is_walkable(pos + *side) && !grid.is_walkable(pos - dir + *side))
+}
+
+/// whether a horizontal jump from `pos` would stop at a jump point
+fn scan_horizontal(pos: IVec2, dir: IVec2, goals: &[IVec2], grid: &Grid) -> bool {
+    let mut current = pos;
+
+    for _ in 0..MAX_JUMP {
+        current += dir;
+        if !grid.is_walkable(current) {return false;}
+        if goals.contains(&current) || has_forced_neighbour(current, dir, grid) {return true;}
+    }
+
+    false
+}
+
+/// jump points are joined by straight lines, walk them back to start to get every tile
+fn fill_path(came_from: &HashMap<IVec2, IVec2>, start: IVec2, end: IVec2) -> Vec<IVec2> {
+    let mut path = vec![end];
+    let mut current = end;
+
+    while current != start {
+        let previous = came_from[&current];
+        let step = (previous - current).signum();
+        while current != previous {
+            current += step;
+            path.push(current);
+        }
+    }
+
+    path
+}
+
+
+#[cfg(test)]
+mod jps_tests {
+    use super::jump_point_search;
+    use crate::{a_star::a_star, grid::{Grid, GridLayout, Object}};
+    use bevy::prelude::*;
+
+    fn grid_with_walls(walls: impl Iterator<Item = IVec2>) -> Grid {
+        let mut grid = Grid::new(Vec2::ZERO, 1.0);
+        for wall in walls {
+            grid.set_object(wall, Some(Object::Wall));
+        }
+        grid
+    }
+
+    fn assert_valid(path: &[IVec2], start: IVec2, grid: &Grid) {
+        assert_eq!(*path.last().unwrap(), start);
+        for pair in path.windows(2) {
+            assert_eq!(GridLayout::Square.distance(pair[0], pair[1]), 1);
+            assert!(grid.is_walkable(pair[0]));
+        }
+    }
+
+    #[test]
+    fn open_ground() {
+        let grid = grid_with_walls(std::iter::empty());
+        let path = jump_point_search(IVec2::new(0, 0), &[IVec2::new(150, -90)], &grid, None).unwrap();
+        assert_valid(&path, IVec2::ZERO, &grid);
+        assert_eq!(path.len(), 241);
+    }
+
+    #[test]
+    fn matches_a_star() {
+        // a room with a few pillars and a wall with two gaps
+        let walls = (-20..=20).flat_map(|i| [IVec2::new(i, -20), IVec2::new(i, 20), IVec2::new(-20, i), IVec2::new(20, i)])
+            .chain((-19..=19).filter(|y| *y != 7 && *y != -12).map(|y| IVec2::new(3, y)))
+            .chain([IVec2::new(-5, 0), IVec2::new(-5, 1), IVec2::new(10, 10), IVec2::new(11, 10), IVec2::new(10, -4)]);
+        let grid = grid_with_walls(walls);
+
+        for (start, end) in [(IVec2::new(-15, 0), IVec2::new(15, 0)), (IVec2::new(-10, -18), IVec2::new(18, 18)), (IVec2::new(19, -19), IVec2::new(-19, 19))] {
+            let jps = jump_point_search(start, &[end], &grid, None).unwrap();
+            let reference = a_star(start, &[end], &grid, None).unwrap();
+            assert_valid(&jps, start, &grid);
+            assert_eq!(jps.len(), reference.len());
+        }
+    }
+}
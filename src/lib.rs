@@ -0,0 +1,75 @@
+pub mod walls;
+pub mod wall_drawing;
+pub mod doors;
+pub mod grid;
+pub mod chunks;
+pub mod building;
+pub mod interaction;
+pub mod asset_loading;
+pub mod a_star;
+pub mod ascii_map;
+pub mod jps;
+pub mod hpa_star;
+pub mod connectivity;
+pub mod pathfinding;
+pub mod path_tasks;
+pub mod reservations;
+pub mod pathfinding_testing;
+pub mod robot;
+pub mod script;
+pub mod item;
+pub mod save;
+pub mod history;
+pub mod blueprint;
+pub mod world_gen;
+pub mod mining;
+pub mod fog;
+
+use bevy::prelude::*;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, States)]
+pub enum AppState {
+    #[default]
+    Setup,
+    Finished,
+}
+
+/// Every plugin of the game itself, on top of bevy's
+pub struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugins((
+                walls::WallPlugin, wall_drawing::WallDrawingPlugin, doors::DoorPlugin, interaction::TileSelectPlugin, grid::GridPlugin,
+                building::BuildingPlugin, interaction::InteractionPlugin, asset_loading::AssetLoadingPlugin, robot::RobotPlugin
+            ))
+            .add_plugins((
+                hpa_star::HierarchicalPathPlugin, pathfinding::PathfindingPlugin, path_tasks::PathTaskPlugin, reservations::ReservationPlugin,
+                script::ScriptPlugin, pathfinding_testing::PathFindTestPlugin, connectivity::ConnectivityPlugin, save::SavePlugin,
+                history::HistoryPlugin, blueprint::BlueprintPlugin, world_gen::WorldGenPlugin, mining::MiningPlugin, fog::FogPlugin
+            ))
+            .add_state::<AppState>();
+    }
+}
+
+
+#[cfg(test)]
+mod app_tests {
+    use super::{AppState, GamePlugin};
+    use bevy::prelude::*;
+
+    /// Systems whose parameters clash, like `Res<Grid>` next to a `ResMut<Grid>`, only panic once the
+    /// schedule is first set up, so every schedule is set up here
+    #[test]
+    fn schedules_build() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_plugins(GamePlugin);
+        assert!(app.world.contains_resource::<State<AppState>>());
+        let mut schedules = app.world.remove_resource::<Schedules>().unwrap();
+        for (label, schedule) in schedules.iter_mut() {
+            schedule.initialize(&mut app.world).unwrap_or_else(|e| panic!("{:?} can't be built: {}", label, e));
+        }
+    }
+}
@@ -1,30 +1,11 @@
-mod walls;
-use walls::WallPlugin;
-mod grid;
-use grid::GridPlugin;
-mod building;
-use building::BuildingPlugin;
-mod interaction;
-use interaction::{TileSelectPlugin, InteractionPlugin};
-mod asset_loading;
-use asset_loading::AssetLoadingPlugin;
-mod a_star;
-mod pathfinding_testing;
-use pathfinding_testing::PathFindTestPlugin;
-mod robot;
-use robot::RobotPlugin;
-mod script;
-mod item;
-
+use robot_tests::{GamePlugin, AppState};
 
 use bevy::{asset::LoadedFolder, prelude::*};
 
 fn main() {
     App::new()
         .add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest())) // prevents blurry sprites
-        .add_plugins((WallPlugin, TileSelectPlugin, GridPlugin, BuildingPlugin, InteractionPlugin, AssetLoadingPlugin, RobotPlugin))
-        .add_plugins(PathFindTestPlugin)
-        .add_state::<AppState>()
+        .add_plugins(GamePlugin)
         .add_systems(OnEnter(AppState::Setup), load_textures)
         .add_systems(Update, check_textures.run_if(in_state(AppState::Setup)))
         .add_systems(OnEnter(AppState::Finished), setup)
@@ -33,13 +14,6 @@ fn main() {
         .run();
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, States)]
-pub enum AppState {
-    #[default]
-    Setup,
-    Finished,
-}
-
 #[derive(Resource, Default)]
 struct SpriteFolder(Handle<LoadedFolder>);
 
@@ -0,0 +1,175 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::fs;
+
+use crate::{
+    grid::{Floor, Grid, GridEntity},
+    item::{Inventory, Item},
+    world_gen::{Terrain, DEPLETED_ORE_COLOUR},
+    AppState
+};
+
+const MINING_PATH: &str = "assets/mining.ron";
+
+
+pub struct MiningPlugin;
+
+impl Plugin for MiningPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(OnEnter(AppState::Setup), load_mining_config)
+            .add_systems(Update, (link_miners, mine_ore).chain().run_if(in_state(AppState::Finished)));
+    }
+}
+
+
+#[derive(Resource, Deserialize, Clone, Debug)]
+pub struct MiningConfig {
+    /// seconds a miner takes to dig out one ore
+    pub seconds_per_ore: f32,
+    /// ore a miner holds before it stops digging
+    pub buffer_size: u32,
+    /// ore in each generated deposit tile, none for deposits that never run out
+    pub vein_yield: Option<u32>,
+}
+
+impl Default for MiningConfig {
+    fn default() -> Self {
+        MiningConfig {
+            seconds_per_ore: 2.0,
+            buffer_size: 20,
+            vein_yield: Some(50),
+        }
+    }
+}
+
+/// Ore left in a deposit tile
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResourceNode {
+    /// none if it never runs out
+    pub remaining: Option<u32>
+}
+
+impl ResourceNode {
+    pub fn is_depleted(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    /// takes one ore out, returning whether there was any left
+    pub fn take(&mut self) -> bool {
+        match self.remaining.as_mut() {
+            None => true,
+            Some(0) => false,
+            Some(remaining) => {*remaining -= 1; true}
+        }
+    }
+}
+
+/// A building that digs ore out of the deposits under it into its inventory
+#[derive(Component, Default)]
+pub struct Miner {
+    /// the deposits it covers
+    pub nodes: Vec<Entity>,
+    /// how much of the next ore has been dug, from 0 to 1
+    pub progress: f32,
+}
+
+
+pub fn load_mining_config(
+    mut commands: Commands
+) {
+    let config = fs::read_to_string(MINING_PATH).map_err(|e| e.to_string())
+        .and_then(|text| ron::from_str::<MiningConfig>(&text).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            warn!("Failed to load {}, using the default mining config: {}", MINING_PATH, e);
+            MiningConfig::default()
+        });
+    commands.insert_resource(config);
+}
+
+/// finds the deposits under newly built miners
+pub fn link_miners(
+    mut miner_query: Query<(&mut Miner, &GridEntity), Added<Miner>>,
+    node_query: Query<(Entity, &Terrain), With<ResourceNode>>
+) {
+    for (mut miner, grid_entity) in miner_query.iter_mut() {
+        miner.nodes = node_query.iter()
+            .filter(|(_, terrain)| grid_entity.contains_cell(terrain.pos))
+            .map(|(entity, _)| entity)
+            .collect();
+    }
+}
+
+pub fn mine_ore(
+    time: Res<Time>,
+    config: Res<MiningConfig>,
+    mut grid: ResMut<Grid>,
+    mut miner_query: Query<(&mut Miner, &mut Inventory)>,
+    mut node_query: Query<(&mut ResourceNode, &Terrain, &mut Sprite)>
+) {
+    for (mut miner, mut buffer) in miner_query.iter_mut() {
+        miner.progress = (miner.progress + time.delta_seconds() / config.seconds_per_ore).min(1.0);
+        if miner.progress < 1.0 || buffer.count(Item::Ore) >= config.buffer_size {continue;}
+
+        for node_entity in miner.nodes.iter() {
+            let Ok((mut node, terrain, mut sprite)) = node_query.get_mut(*node_entity) else {continue};
+            if !node.take() {continue;}
+
+            if node.is_depleted() {
+                sprite.color = DEPLETED_ORE_COLOUR;
+                grid.set_floor(terrain.pos, Floor::DepletedOre);
+            }
+            buffer.add(Item::Ore, 1);
+            miner.progress = 0.0;
+            break;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod mining_tests {
+    use super::{mine_ore, MiningConfig, Miner, ResourceNode};
+    use crate::{
+        grid::{Floor, Grid},
+        item::{Inventory, Item},
+        world_gen::{Terrain, DEPLETED_ORE_COLOUR}
+    };
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+    use std::time::Duration;
+
+    #[test]
+    fn yields() {
+        let mut infinite = ResourceNode {remaining: None};
+        assert!((0..1000).all(|_| infinite.take()));
+
+        let mut finite = ResourceNode {remaining: Some(2)};
+        assert!(finite.take() && finite.take());
+        assert!(finite.is_depleted());
+        assert!(!finite.take());
+    }
+
+    #[test]
+    fn miners_empty_their_deposits() {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.insert_resource(MiningConfig {seconds_per_ore: 1.0, buffer_size: 3, vein_yield: None});
+        let mut grid = Grid::new(Vec2::ZERO, 1.0);
+
+        let pos = IVec2::new(4, 4);
+        grid.set_floor(pos, Floor::OreDeposit);
+        world.insert_resource(grid);
+        let node = world.spawn((ResourceNode {remaining: Some(2)}, Terrain {pos}, Sprite::default())).id();
+        let miner = world.spawn((Miner {nodes: vec![node], progress: 0.0}, Inventory::default())).id();
+
+        for _ in 0..4 {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            world.run_system_once(mine_ore);
+        }
+
+        assert_eq!(world.get::<Inventory>(miner).unwrap().count(Item::Ore), 2);
+        assert!(world.get::<ResourceNode>(node).unwrap().is_depleted());
+        assert_eq!(world.get::<Sprite>(node).unwrap().color, DEPLETED_ORE_COLOUR);
+        assert_eq!(world.resource::<Grid>()[pos].floor, Floor::DepletedOre);
+    }
+}
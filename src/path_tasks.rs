@@ -0,0 +1,127 @@
+use bevy::{prelude::*, tasks::{AsyncComputeTaskPool, Task, block_on}};
+use std::sync::Arc;
+
+use crate::{grid::Grid, hpa_star::{update_hierarchical_graph, HierarchicalGraph}, pathfinding::{PathfindingStrategy, SearchTrace}, robot::{PathFollower, RobotState}, AppState};
+
+
+pub struct PathTaskPlugin;
+
+impl Plugin for PathTaskPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, poll_path_tasks.run_if(in_state(AppState::Finished)))
+            // after the hierarchical graph has caught up with this frame's changes, so searches can share its clusters
+            .add_systems(PostUpdate, spawn_path_tasks.after(update_hierarchical_graph).run_if(in_state(AppState::Finished)));
+    }
+}
+
+
+/// What the most recent finished search for this entity looked at, kept for the debug overlay
+#[derive(Component, Clone)]
+pub struct LastSearch {
+    pub start: IVec2,
+    pub path: Option<Vec<IVec2>>,
+    pub trace: SearchTrace,
+}
+
+/// Asks for a path to be planned for this entity to the nearest of the targets,
+/// it is removed again once the search has been started
+#[derive(Component)]
+pub struct PathRequest {
+    pub start: IVec2,
+    pub targets: Vec<IVec2>
+}
+
+/// A search running on the async compute pool against a snapshot of the grid
+#[derive(Component)]
+pub struct PathTask {
+    task: Task<(Option<Vec<IVec2>>, SearchTrace)>,
+    start: IVec2,
+    targets: Vec<IVec2>,
+    /// the walkability generation of the snapshot, see `Grid::walkability_generation`
+    generation: u64,
+}
+
+
+pub fn spawn_path_tasks(
+    mut commands: Commands,
+    mut requests: Query<(Entity, &PathRequest, &mut RobotState)>,
+    grid: Res<Grid>,
+    graph: Res<HierarchicalGraph>,
+    strategy: Res<PathfindingStrategy>,
+    mut snapshot: Local<Option<Arc<Grid>>>,
+) {
+    // the graph hasn't heard of these yet, so searches wait a frame for it
+    if requests.is_empty() || grid.has_pending_walkability_changes() {return;}
+    let pool = AsyncComputeTaskPool::get();
+    let generation = grid.walkability_generation();
+    let graph = graph.at_generation(generation);
+    // every search shares one copy of the grid, taken again only once somewhere has become walkable or stopped being so
+    if snapshot.as_ref().is_none_or(|snapshot| snapshot.walkability_generation() != generation) {
+        *snapshot = Some(Arc::new(grid.path_snapshot()));
+    }
+
+    for (entity, request, mut brain_state) in requests.iter_mut() {
+        let (start, targets) = (request.start, request.targets.clone());
+        let grid_snapshot = snapshot.clone().unwrap();
+        let mut pathfinder = strategy.pathfinder(&graph, grid.layout);
+        let task = pool.spawn(async move {
+            let mut trace = SearchTrace::default();
+            let path = pathfinder.find_path_traced(start, &targets, &grid_snapshot, Some(&mut trace));
+            (path, trace)
+        });
+
+        *brain_state = RobotState::Planning;
+        commands.entity(entity)
+            .remove::<PathRequest>()
+            .insert(PathTask {
+                task,
+                start,
+                targets: request.targets.clone(),
+                generation,
+            });
+    }
+}
+
+pub fn poll_path_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut PathTask, &mut PathFollower, &mut RobotState)>,
+    grid: Res<Grid>,
+) {
+    for (entity, mut path_task, mut follower, mut brain_state) in tasks.iter_mut() {
+        if !path_task.task.is_finished() {continue;}
+        let (result, trace) = block_on(&mut path_task.task);
+        commands.entity(entity)
+            .remove::<PathTask>()
+            .insert(LastSearch {
+                start: path_task.start,
+                path: result.clone(),
+                trace
+            });
+
+        // somewhere became walkable or stopped being so while we were searching, only keep the path if it still is.
+        // Robots and fog coming and going don't count, so a search that found nothing isn't retried for nothing
+        let stale = grid.walkability_generation() != path_task.generation && match result.as_ref() {
+            Some(path) => path.iter().take(path.len() - 1).any(|cell| !grid.is_walkable(*cell)),
+            None => true
+        };
+        if stale {
+            commands.entity(entity).insert(PathRequest {
+                start: path_task.start,
+                targets: path_task.targets.clone()
+            });
+            continue;
+        }
+
+        match result {
+            Some(mut path) => {
+                path.pop(); // the tile the robot is already on
+                follower.path = path;
+                follower.plan.clear();
+                follower.targets = path_task.targets.clone();
+                *brain_state = RobotState::Running;
+            },
+            None => {*brain_state = RobotState::Stuck}
+        }
+    }
+}
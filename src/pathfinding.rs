@@ -0,0 +1,86 @@
+use bevy::prelude::*;
+
+use crate::{a_star::AStar, grid::{Grid, GridLayout}, hpa_star::HierarchicalGraph, jps::JumpPointSearch};
+
+
+pub struct PathfindingPlugin;
+
+impl Plugin for PathfindingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<PathfindingStrategy>()
+            .add_systems(Update, cycle_pathfinding_strategy);
+    }
+}
+
+
+/// The tiles a search looked at, for debugging
+#[derive(Default, Clone)]
+pub struct SearchTrace {
+    /// nodes that were expanded
+    pub explored: Vec<IVec2>,
+    /// nodes still waiting on the open list when the search stopped
+    pub frontier: Vec<IVec2>,
+}
+
+/// Common interface for the different ways of finding a path through the grid
+pub trait Pathfinder: Send {
+    /// Finds a path to whichever of the goals is nearest, recording what was searched if given a trace.
+    ///
+    /// The path is returned from the chosen goal back to start, so that followers can pop the next step off the end
+    fn find_path_traced(&mut self, start: IVec2, goals: &[IVec2], grid: &Grid, trace: Option<&mut SearchTrace>) -> Option<Vec<IVec2>>;
+
+    fn find_path_to_any(&mut self, start: IVec2, goals: &[IVec2], grid: &Grid) -> Option<Vec<IVec2>> {
+        self.find_path_traced(start, goals, grid, None)
+    }
+
+    fn find_path(&mut self, start: IVec2, end: IVec2, grid: &Grid) -> Option<Vec<IVec2>> {
+        self.find_path_to_any(start, &[end], grid)
+    }
+}
+
+/// Which pathfinder robots use, cycled at runtime with P
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathfindingStrategy {
+    AStar,
+    JumpPoint,
+    #[default]
+    Hierarchical,
+}
+
+impl PathfindingStrategy {
+    pub fn next(&self) -> Self {
+        match self {
+            PathfindingStrategy::AStar => PathfindingStrategy::JumpPoint,
+            PathfindingStrategy::JumpPoint => PathfindingStrategy::Hierarchical,
+            PathfindingStrategy::Hierarchical => PathfindingStrategy::AStar,
+        }
+    }
+
+    /// builds a pathfinder that owns everything it needs, so it can be sent off to another thread. The hierarchical
+    /// one shares its clusters with `graph`. Jump points and the hierarchical graph only work on squares, so hex grids always get A*
+    pub fn pathfinder(&self, graph: &HierarchicalGraph, layout: GridLayout) -> Box<dyn Pathfinder> {
+        if layout == GridLayout::Hex {return Box::new(AStar);}
+        match self {
+            PathfindingStrategy::AStar => Box::new(AStar),
+            PathfindingStrategy::JumpPoint => Box::new(JumpPointSearch),
+            PathfindingStrategy::Hierarchical => Box::new(graph.clone()),
+        }
+    }
+}
+
+pub fn cycle_pathfinding_strategy(
+    keyboard: Res<Input<KeyCode>>,
+    mut strategy: ResMut<PathfindingStrategy>
+) {
+    if keyboard.just_pressed(KeyCode::P) {
+        *strategy = strategy.next();
+        info!("Pathfinding with {:?}", *strategy);
+    }
+}
+
+
+/// the manhattan distance to the closest of the goals
+pub fn nearest_goal_distance(cell: IVec2, goals: &[IVec2]) -> u32 {
+    goals.iter().map(|goal| GridLayout::Square.distance(cell, *goal)).min().unwrap_or(0)
+}
@@ -1,18 +1,30 @@
 use bevy::prelude::*;
 
-use crate::{a_star::a_star, grid::{Grid, grid_to_space, TileState, GridScale}, AppState};
+use crate::{
+    grid::{Grid, grid_to_space, GridScale},
+    hpa_star::HierarchicalGraph,
+    interaction::TileSelectIndicator,
+    path_tasks::LastSearch,
+    pathfinding::{PathfindingStrategy, SearchTrace},
+    robot::Robot,
+    AppState
+};
 
 
-const START: IVec2 = IVec2{x: -8, y: -2};
-const END: IVec2 = IVec2{x: 7, y: 3};
+const EXPLORED_COLOUR: Color = Color::rgba(0.2, 0.4, 1.0, 0.35);
+const FRONTIER_COLOUR: Color = Color::rgba(1.0, 0.85, 0.1, 0.5);
 
 pub struct PathFindTestPlugin;
 
 impl Plugin for PathFindTestPlugin {
     fn build(&self, app: &mut App) {
         app
+            .init_resource::<PathDebugOverlay>()
+            .init_resource::<OverlaySearch>()
             .add_systems(OnEnter(AppState::Finished), setup)
-            .add_systems(Update, run_path_finding.run_if(in_state(AppState::Finished)));
+            .add_systems(Update, (toggle_overlay, pick_overlay_endpoints, select_overlay_robot, update_overlay_search, draw_overlay)
+                .chain()
+                .run_if(in_state(AppState::Finished)));
     }
 }
 
@@ -26,80 +38,168 @@ pub struct PathFindSprites {
     pub end: Handle<Image>,
     pub other: Handle<Image>
 }
-#[derive(Resource)]
-pub struct PathFindCount(usize);
+
+/// Debug view of a path search, toggled with O.
+///
+/// Right click picks the start of a test search and shift + right click its end,
+/// Tab instead cycles through the robots to show their most recent search.
+#[derive(Resource, Default)]
+pub struct PathDebugOverlay {
+    pub enabled: bool,
+    pub start: Option<IVec2>,
+    pub end: Option<IVec2>,
+    pub robot: Option<Entity>,
+}
+
+/// The search the overlay is currently showing
+#[derive(Resource, Default)]
+pub struct OverlaySearch(pub Option<LastSearch>);
 
 
 pub fn setup(
     mut commands: Commands,
-    mut grid: ResMut<Grid>,
-    grid_scale: Res<GridScale>,
     asset_server: ResMut<AssetServer>,
 ) {
-
-    grid.tiles.insert(START, TileState::InteractionPoint);
-    grid.tiles.insert(END, TileState::InteractionPoint);
-
-    let start_sprite = asset_server.load("robot_game/sprites/misc/PathFindTestStart.png");
-    let end_sprite = asset_server.load("robot_game/sprites/misc/PathFindTestEnd.png");
-    let path_sprite = asset_server.load("robot_game/sprites/misc/PathFindTestPath.png");
-
-    commands.spawn(SpriteBundle {
-        texture: start_sprite.clone(),
-        transform: Transform {
-            translation: grid_to_space(START, &grid).extend(0.0),
-            scale: grid_scale.0,
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    commands.spawn(SpriteBundle {
-        texture: end_sprite.clone(),
-        transform: Transform {
-            translation: grid_to_space(END, &grid).extend(0.0),
-            scale: grid_scale.0,
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-
     commands.insert_resource(PathFindSprites {
-        start: start_sprite,
-        end: end_sprite,
-        other: path_sprite
+        start: asset_server.load("robot_game/sprites/misc/PathfindTestStart.png"),
+        end: asset_server.load("robot_game/sprites/misc/PathfindTestEnd.png"),
+        other: asset_server.load("robot_game/sprites/misc/PathfindTestPath.png")
     });
+}
 
-    commands.insert_resource(PathFindCount(1));
+pub fn toggle_overlay(
+    keyboard: Res<Input<KeyCode>>,
+    mut overlay: ResMut<PathDebugOverlay>
+) {
+    if keyboard.just_pressed(KeyCode::O) {
+        overlay.enabled = !overlay.enabled;
+    }
 }
 
+pub fn pick_overlay_endpoints(
+    keyboard: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    tile_select: Query<&TileSelectIndicator>,
+    mut overlay: ResMut<PathDebugOverlay>
+) {
+    if !overlay.enabled || !mouse.just_pressed(MouseButton::Right) {return;}
+    let tile = tile_select.single().pos;
 
-pub fn run_path_finding(
+    if keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        overlay.end = Some(tile);
+    } else {
+        overlay.start = Some(tile);
+    }
+    overlay.robot = None;
+}
+
+pub fn select_overlay_robot(
     keyboard: Res<Input<KeyCode>>,
+    robot_query: Query<Entity, With<Robot>>,
+    mut overlay: ResMut<PathDebugOverlay>
+) {
+    if !overlay.enabled || !keyboard.just_pressed(KeyCode::Tab) {return;}
+
+    let mut robots: Vec<Entity> = robot_query.iter().collect();
+    robots.sort();
+    let next = match overlay.robot.and_then(|current| robots.iter().position(|robot| *robot == current)) {
+        Some(i) => robots.get(i + 1).copied(),
+        None => robots.first().copied(),
+    };
+    overlay.robot = next;
+}
+
+pub fn update_overlay_search(
+    overlay: Res<PathDebugOverlay>,
+    mut shown: ResMut<OverlaySearch>,
+    searches: Query<Ref<LastSearch>>,
+    grid: Res<Grid>,
+    graph: Res<HierarchicalGraph>,
+    strategy: Res<PathfindingStrategy>
+) {
+    if !overlay.enabled {return;}
+
+    if let Some(robot) = overlay.robot {
+        let Ok(search) = searches.get(robot) else {
+            if shown.0.is_some() {shown.0 = None;}
+            return;
+        };
+        if overlay.is_changed() || search.is_changed() {
+            shown.0 = Some(search.clone());
+        }
+        return;
+    }
+
+    let (Some(start), Some(end)) = (overlay.start, overlay.end) else {
+        if overlay.is_changed() {
+            shown.0 = overlay.start.map(|start| LastSearch {start, path: None, trace: SearchTrace::default()});
+        }
+        return;
+    };
+    if overlay.is_changed() || grid.is_changed() || strategy.is_changed() {
+        let mut trace = SearchTrace::default();
+        // the live grid can be ahead of the graph, so this search keeps its clusters to itself
+        let path = strategy.pathfinder(&graph.detached(), grid.layout).find_path_traced(start, &[end], &grid, Some(&mut trace));
+        shown.0 = Some(LastSearch {start, path, trace});
+    }
+}
+
+/// redraws the overlay, only when what it shows has changed
+pub fn draw_overlay(
     mut commands: Commands,
+    overlay: Res<PathDebugOverlay>,
+    shown: Res<OverlaySearch>,
     paths: Query<Entity, With<PathTag>>,
     grid: Res<Grid>,
     grid_scale: Res<GridScale>,
     sprites: Res<PathFindSprites>,
-    mut count: ResMut<PathFindCount>
 ) {
-    if !keyboard.just_pressed(KeyCode::Space) {
-        for entity in paths.iter() {
-            commands.entity(entity).despawn_recursive();
-        };
-        if let Some(points) = a_star(START, END, &grid, count.0) {
-            for i in 1..points.len() - 1 {
-                commands.spawn(SpriteBundle {
-                    texture: sprites.other.clone(),
-                    transform: Transform {
-                        translation: grid_to_space(points[i], &grid).extend(-1.0),
-                        scale: grid_scale.0,
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(PathTag);
-            }
-            count.0 += 1;
+    if !overlay.is_changed() && !shown.is_changed() {return;}
+
+    for entity in paths.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let Some(search) = shown.0.as_ref().filter(|_| overlay.enabled) else {return};
+
+    let mut spawn_square = |cell: IVec2, colour: Color, z: f32| {
+        commands.spawn(SpriteBundle {
+            sprite: Sprite {
+                color: colour,
+                custom_size: Some(Vec2::splat(grid.tile_size)),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(grid_to_space(cell, &grid).extend(z)),
+            ..Default::default()
+        })
+        .insert(PathTag);
+    };
+    for cell in search.trace.explored.iter() {
+        spawn_square(*cell, EXPLORED_COLOUR, 0.5);
+    }
+    for cell in search.trace.frontier.iter() {
+        spawn_square(*cell, FRONTIER_COLOUR, 0.5);
+    }
+
+    let mut spawn_sprite = |cell: IVec2, texture: &Handle<Image>| {
+        commands.spawn(SpriteBundle {
+            texture: texture.clone(),
+            transform: Transform {
+                translation: grid_to_space(cell, &grid).extend(0.6),
+                scale: grid_scale.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(PathTag);
+    };
+    if let Some(points) = search.path.as_ref() {
+        for point in points.iter().take(points.len() - 1).skip(1) {
+            spawn_sprite(*point, &sprites.other);
         }
     }
-}
\ No newline at end of file
+    spawn_sprite(search.start, &sprites.start);
+    let end = search.path.as_ref().map(|points| points[0]).or(overlay.end.filter(|_| overlay.robot.is_none()));
+    if let Some(end) = end {
+        spawn_sprite(end, &sprites.end);
+    }
+}
@@ -0,0 +1,181 @@
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use crate::grid::Grid;
+
+
+pub struct ReservationPlugin;
+
+impl Plugin for ReservationPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ReservationTable>();
+    }
+}
+
+
+/// Who is standing where and when.
+///
+/// Running robots reserve every (tile, tick) of their windowed plan, plus the edge they use between two ticks
+/// so that a robot can never walk head-on into another one. Robots that are not moving are parked on their tile
+/// for all future ticks.
+#[derive(Resource, Default)]
+pub struct ReservationTable {
+    pub tick: u32,
+    cells: HashMap<(IVec2, u32), Entity>,
+    /// (from, to, tick the move starts)
+    edges: HashMap<(IVec2, IVec2, u32), Entity>,
+    parked: HashMap<IVec2, Entity>,
+}
+
+impl ReservationTable {
+    /// moves time on one tick and forgets the reservations that are now in the past
+    pub fn advance(&mut self) {
+        self.tick += 1;
+        let tick = self.tick;
+        self.cells.retain(|(_, t), _| *t >= tick);
+        self.edges.retain(|(_, _, t), _| *t >= tick);
+    }
+
+    /// returns whoever other than `entity` is in the cell at the given tick
+    pub fn occupant(&self, cell: IVec2, tick: u32, entity: Entity) -> Option<Entity> {
+        let other = self.cells.get(&(cell, tick)).or_else(|| self.parked.get(&cell));
+        other.copied().filter(|other| *other != entity)
+    }
+
+    pub fn is_free(&self, cell: IVec2, tick: u32, entity: Entity) -> bool {
+        self.occupant(cell, tick, entity).is_none()
+    }
+
+    /// whether `entity` can go from one cell to another between `tick` and `tick + 1`
+    pub fn can_move(&self, from: IVec2, to: IVec2, tick: u32, entity: Entity) -> bool {
+        if !self.is_free(to, tick + 1, entity) {return false;}
+        // someone coming the other way along the same edge
+        self.edges.get(&(to, from, tick)).is_none_or(|other| *other == entity)
+    }
+
+    /// reserves a plan where `plan[0]` is the cell the entity is in at `start_tick`
+    pub fn reserve_plan(&mut self, entity: Entity, start_tick: u32, plan: &[IVec2]) {
+        for (i, cell) in plan.iter().enumerate() {
+            let tick = start_tick + i as u32;
+            self.cells.insert((*cell, tick), entity);
+            if let Some(next) = plan.get(i + 1) {
+                self.edges.insert((*cell, *next, tick), entity);
+            }
+        }
+    }
+
+    pub fn release(&mut self, entity: Entity) {
+        self.cells.retain(|_, other| *other != entity);
+        self.edges.retain(|_, other| *other != entity);
+    }
+
+    pub fn park(&mut self, entity: Entity, cell: IVec2) {
+        self.parked.insert(cell, entity);
+    }
+
+    pub fn clear_parked(&mut self) {
+        self.parked.clear();
+    }
+}
+
+
+/// Space-time A* that plans around the reservations of other robots for at most `window` ticks.
+///
+/// Waiting in place is a valid move. If the goal cannot be reached within the window the plan to the
+/// explored cell closest to the goal is returned instead. The plan starts with `start` at the table's current tick.
+pub fn cooperative_a_star(start: IVec2, goal: IVec2, grid: &Grid, table: &ReservationTable, entity: Entity, window: u32) -> Vec<IVec2> {
+    let tick = table.tick;
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<(IVec2, u32), IVec2> = HashMap::new();
+    let mut closed = HashSet::new();
+    let distance = |cell: IVec2| grid.layout.distance(cell, goal);
+    let mut best = (distance(start), 0, start);
+    open.push(Reverse((distance(start), 0, start.to_array())));
+
+    while let Some(Reverse((_, dt, pos))) = open.pop() {
+        let pos = IVec2::from_array(pos);
+        if !closed.insert((pos, dt)) {continue;}
+
+        let h = distance(pos);
+        if (h, dt) < (best.0, best.1) {best = (h, dt, pos);}
+        if pos == goal && table.is_free(goal, tick + dt + 1, entity) {break;}
+        if dt == window {continue;}
+
+        for next in std::iter::once(pos).chain(grid.layout.neighbours().iter().map(|offset| pos + *offset)) {
+            if next != pos && !grid.is_walkable(next) {continue;}
+            if !table.can_move(pos, next, tick + dt, entity) {continue;}
+            if closed.contains(&(next, dt + 1)) {continue;}
+
+            came_from.entry((next, dt + 1)).or_insert(pos);
+            open.push(Reverse((dt + 1 + distance(next), dt + 1, next.to_array())));
+        }
+    }
+
+    let (_, mut dt, mut pos) = best;
+    let mut plan = vec![pos];
+    while dt > 0 {
+        pos = came_from[&(pos, dt)];
+        dt -= 1;
+        plan.push(pos);
+    }
+    plan.reverse();
+    plan
+}
+
+
+#[cfg(test)]
+mod reservation_tests {
+    use super::{cooperative_a_star, ReservationTable, IVec2, Entity};
+    use crate::grid::{Grid, Object};
+
+    /// a corridor along y = 0 from x = 0 to 8 with a side pocket at (6, 1)
+    fn corridor() -> Grid {
+        let mut grid = Grid::new(bevy::math::Vec2::ZERO, 1.0);
+        for x in -1..=9 {
+            grid.set_object(IVec2::new(x, -1), Some(Object::Wall));
+            if x != 6 {grid.set_object(IVec2::new(x, 1), Some(Object::Wall));}
+        }
+        grid.set_object(IVec2::new(6, 2), Some(Object::Wall));
+        grid.set_object(IVec2::new(-1, 0), Some(Object::Wall));
+        grid.set_object(IVec2::new(9, 0), Some(Object::Wall));
+        grid
+    }
+
+    #[test]
+    fn head_on_in_corridor() {
+        let grid = corridor();
+        let mut table = ReservationTable::default();
+        let first = Entity::from_raw(1);
+        let second = Entity::from_raw(2);
+
+        let first_plan = cooperative_a_star(IVec2::new(0, 0), IVec2::new(7, 0), &grid, &table, first, 32);
+        table.reserve_plan(first, 0, &first_plan);
+        assert_eq!(first_plan.len(), 8);
+
+        let second_plan = cooperative_a_star(IVec2::new(8, 0), IVec2::new(0, 0), &grid, &table, second, 32);
+        assert_eq!(*second_plan.last().unwrap(), IVec2::new(0, 0));
+        assert!(second_plan.contains(&IVec2::new(6, 1)));
+
+        for tick in 0..second_plan.len().max(first_plan.len()) {
+            let a = first_plan[tick.min(first_plan.len() - 1)];
+            let b = second_plan[tick.min(second_plan.len() - 1)];
+            assert_ne!(a, b, "collision at tick {}", tick);
+            if tick > 0 {
+                let previous_a = first_plan[(tick - 1).min(first_plan.len() - 1)];
+                let previous_b = second_plan[(tick - 1).min(second_plan.len() - 1)];
+                assert!(!(a == previous_b && b == previous_a), "swap at tick {}", tick);
+            }
+        }
+    }
+
+    #[test]
+    fn parked_robots_block() {
+        let grid = corridor();
+        let mut table = ReservationTable::default();
+        table.park(Entity::from_raw(1), IVec2::new(6, 0));
+
+        let plan = cooperative_a_star(IVec2::new(0, 0), IVec2::new(8, 0), &grid, &table, Entity::from_raw(2), 32);
+        assert_eq!(*plan.last().unwrap(), IVec2::new(5, 0));
+    }
+}
@@ -1,6 +1,13 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
 
-use crate::{asset_loading::RobotAtlasHandle, grid::{grid_to_space, Grid, GridScale}, AppState};
+use crate::{asset_loading::RobotAtlasHandle, grid::{grid_to_space, Grid, GridScale, TileChanged}, interaction::TileSelectIndicator, item::Inventory, path_tasks::PathRequest, reservations::{cooperative_a_star, ReservationTable}, AppState};
+
+/// seconds between robots stepping to the next tile of their path
+const MOVE_INTERVAL: f32 = 0.2;
+/// how many ticks ahead robots reserve tiles for
+const PLAN_WINDOW: u32 = 16;
+/// ticks a robot can be unable to move before someone has to give way
+const DEADLOCK_TICKS: u32 = 6;
 
 
 pub struct RobotPlugin;
@@ -8,8 +15,9 @@ pub struct RobotPlugin;
 impl Plugin for RobotPlugin {
     fn build(&self, app: &mut App) {
         app
+            .insert_resource(RobotMoveTimer(Timer::from_seconds(MOVE_INTERVAL, TimerMode::Repeating)))
             .add_systems(OnEnter(AppState::Finished), spawn_robot)
-            .add_systems(Update, update_robot_sprites.run_if(in_state(AppState::Finished)));
+            .add_systems(Update, (update_robot_sprites, spawn_robot_at_cursor, send_robots_to_cursor, replan_on_tile_changes, follow_paths, track_occupants.after(follow_paths)).run_if(in_state(AppState::Finished)));
     }
 }
 
@@ -18,23 +26,25 @@ impl Plugin for RobotPlugin {
 #[derive(Component)]
 pub enum RobotState {
     Idle,
+    /// waiting for a path search to come back from the task pool
+    Planning,
     Running,
     Stuck
 }
 
-#[derive(Component)]
+#[derive(Resource)]
+pub struct RobotMoveTimer(pub Timer);
+
+#[derive(Component, Default)]
 pub struct PathFollower {
+    /// the route to the target, next step last
     pub path: Vec<IVec2>,
-    pub target: IVec2
-}
-
-impl Default for PathFollower {
-    fn default() -> Self {
-        PathFollower {
-            path: Vec::new(),
-            target: IVec2::ZERO
-        }
-    }
+    /// the tiles the path was planned towards, it ends at whichever was nearest
+    pub targets: Vec<IVec2>,
+    /// the tiles reserved for the coming ticks, next step last
+    pub plan: Vec<IVec2>,
+    /// how many ticks in a row the robot could not move
+    pub waiting: u32
 }
 
 #[derive(Component)]
@@ -48,7 +58,8 @@ pub struct RobotBundle {
     pub sprite: SpriteSheetBundle,
     pub robot: Robot,
     pub path_follow: PathFollower,
-    pub brain_state: RobotState
+    pub brain_state: RobotState,
+    pub inventory: Inventory
 }
 
 pub fn spawn_robot(
@@ -57,24 +68,50 @@ pub fn spawn_robot(
     grid: Res<Grid>,
     grid_scale: Res<GridScale>
 ) {
+    spawn_robot_at(&mut commands, &grid, &grid_scale, IVec2::ZERO, &atlas);
+}
+
+pub fn spawn_robot_at(
+    commands: &mut Commands,
+    grid: &Grid,
+    grid_scale: &GridScale,
+    location: IVec2,
+    atlas_handle: &RobotAtlasHandle
+) -> Entity {
     commands.spawn( RobotBundle {
         sprite: SpriteSheetBundle {
-            texture_atlas: atlas.0.clone(),
+            texture_atlas: atlas_handle.0.clone(),
             sprite: TextureAtlasSprite::new(0),
             transform: Transform {
-                translation: grid_to_space(IVec2::ZERO, &grid).extend(0.0),
+                translation: grid_to_space(location, grid).extend(0.0),
                 scale: grid_scale.0,
                 ..Default::default()
             },
             ..Default::default()
         },
         robot: Robot {
-            location: IVec2::ZERO
+            location
         },
         path_follow: PathFollower::default(),
-        brain_state: RobotState::Stuck
+        brain_state: RobotState::Stuck,
+        inventory: Inventory::default()
     }
-    );
+    ).id()
+}
+
+pub fn spawn_robot_at_cursor(
+    keyboard: Res<Input<KeyCode>>,
+    mut commands: Commands,
+    tile_select: Query<&TileSelectIndicator>,
+    atlas: Res<RobotAtlasHandle>,
+    grid: Res<Grid>,
+    grid_scale: Res<GridScale>
+) {
+    if !keyboard.just_pressed(KeyCode::N) {return;}
+    let location = tile_select.single().pos;
+    if !grid.is_walkable(location) || grid[location].occupant.is_some() {return;}
+
+    spawn_robot_at(&mut commands, &grid, &grid_scale, location, &atlas);
 }
 
 
@@ -83,7 +120,7 @@ pub fn update_robot_sprites(
 ) {
     for (brain_state, mut sprite) in sprite_query.iter_mut() {
         match brain_state {
-            RobotState::Idle => {sprite.index = 1},
+            RobotState::Idle | RobotState::Planning => {sprite.index = 1},
             RobotState::Running => {sprite.index = 0},
             RobotState::Stuck => {sprite.index = 2}
         }
@@ -91,4 +128,195 @@ pub fn update_robot_sprites(
 }
 
 
+pub fn send_robots_to_cursor(
+    keyboard: Res<Input<KeyCode>>,
+    mut commands: Commands,
+    tile_select: Query<&TileSelectIndicator>,
+    robot_query: Query<(Entity, &Robot, &RobotState)>
+) {
+    if !keyboard.just_pressed(KeyCode::G) {return;}
+    let target = tile_select.single().pos;
+
+    for (entity, robot, brain_state) in robot_query.iter() {
+        if matches!(brain_state, RobotState::Planning) {continue;}
+        commands.entity(entity).insert(PathRequest {
+            start: robot.location,
+            targets: vec![target]
+        });
+    }
+}
+
+pub fn follow_paths(
+    time: Res<Time>,
+    mut timer: ResMut<RobotMoveTimer>,
+    mut commands: Commands,
+    mut robot_query: Query<(Entity, &mut Robot, &mut PathFollower, &mut RobotState, &mut Transform)>,
+    grid: Res<Grid>,
+    mut table: ResMut<ReservationTable>
+) {
+    if !timer.0.tick(time.delta()).just_finished() {return;}
+
+    table.advance();
+    table.clear_parked();
+    for (entity, robot, _, brain_state, _) in robot_query.iter() {
+        if !matches!(brain_state, RobotState::Running) {table.park(entity, robot.location);}
+    }
+
+    // lower entity index plans first and so gets right of way
+    let mut robots: Vec<_> = robot_query.iter_mut().collect();
+    robots.sort_by_key(|(entity, ..)| *entity);
+    let mut blocked = Vec::new();
+
+    for (entity, robot, follower, brain_state, transform) in robots.iter_mut() {
+        if !matches!(**brain_state, RobotState::Running) {continue;}
+
+        if follower.plan.is_empty() {
+            let Some(waypoint) = follower.path.iter().rev().nth(PLAN_WINDOW as usize / 2).or(follower.path.first()).copied() else {
+                **brain_state = RobotState::Idle;
+                continue;
+            };
+            table.release(*entity);
+            let mut plan = cooperative_a_star(robot.location, waypoint, &grid, &table, *entity, PLAN_WINDOW);
+            let tick = table.tick;
+            table.reserve_plan(*entity, tick, &plan);
+
+            if plan.len() == 1 {
+                follower.waiting += 1;
+                if follower.waiting > DEADLOCK_TICKS {
+                    follower.waiting = 0;
+                    blocked.push((*entity, robot.location, follower.path.last().copied()));
+                }
+                continue;
+            }
+            follower.waiting = 0;
+            plan.reverse();
+            plan.pop(); // the cell we are standing on
+            follower.plan = plan;
+        }
+
+        let next = follower.plan.pop().unwrap();
+        // something was built on the path since it was planned
+        if !grid.is_walkable(next) {
+            follower.path.clear();
+            follower.plan.clear();
+            table.release(*entity);
+            commands.entity(*entity).insert(PathRequest {
+                start: robot.location,
+                targets: follower.targets.clone()
+            });
+            continue;
+        }
+
+        robot.location = next;
+        transform.translation = grid_to_space(next, &grid).extend(transform.translation.z);
+        if let Some(i) = follower.path.iter().position(|cell| *cell == next) {
+            follower.path.truncate(i);
+        }
+        if follower.path.is_empty() && follower.plan.is_empty() {
+            **brain_state = RobotState::Idle;
+            table.release(*entity);
+        }
+    }
+
+    for (entity, location, next_step) in blocked {
+        resolve_deadlock(entity, location, next_step, &mut robots, &grid, &mut table, &mut commands);
+    }
+}
+
+/// Called for a robot that has not been able to move for a while.
+///
+/// If another robot is standing in the way, whichever of the two has the lower priority steps aside to a free
+/// neighbouring tile (idle robots always give way). Otherwise the route itself is planned again.
+/// the parts of a robot `move_robots` changes
+type MovingRobot<'a> = (Entity, Mut<'a, Robot>, Mut<'a, PathFollower>, Mut<'a, RobotState>, Mut<'a, Transform>);
+
+fn resolve_deadlock(
+    entity: Entity,
+    location: IVec2,
+    next_step: Option<IVec2>,
+    robots: &mut [MovingRobot],
+    grid: &Grid,
+    table: &mut ReservationTable,
+    commands: &mut Commands
+) {
+    let blocker = next_step.and_then(|cell| table.occupant(cell, table.tick + 1, entity));
+    let yielder = match blocker.and_then(|blocker| robots.iter().position(|robot| robot.0 == blocker)) {
+        Some(i) if !matches!(*robots[i].3, RobotState::Running) || robots[i].0 > entity => i,
+        Some(_) => robots.iter().position(|robot| robot.0 == entity).unwrap(),
+        None => {
+            let (_, _, follower, _, _) = robots.iter_mut().find(|robot| robot.0 == entity).unwrap();
+            follower.plan.clear();
+            table.release(entity);
+            commands.entity(entity).insert(PathRequest {
+                start: location,
+                targets: follower.targets.clone()
+            });
+            return;
+        }
+    };
+
+    let (yield_entity, robot, follower, brain_state, _) = &mut robots[yielder];
+    let free = grid.layout.neighbours().iter()
+        .map(|dir| robot.location + *dir)
+        .find(|cell| Some(*cell) != next_step && *cell != location && grid.is_walkable(*cell)
+            && table.can_move(robot.location, *cell, table.tick, *yield_entity));
+    let Some(free) = free else {return};
 
+    table.release(*yield_entity);
+    table.reserve_plan(*yield_entity, table.tick, &[robot.location, free]);
+    follower.plan = vec![free];
+    follower.waiting = 0;
+    **brain_state = RobotState::Running;
+}
+
+/// Plans again for robots whose path was just built over, and for stuck robots once something opens up
+pub fn replan_on_tile_changes(
+    mut commands: Commands,
+    mut tile_changes: EventReader<TileChanged>,
+    mut robot_query: Query<(Entity, &Robot, &mut PathFollower, &RobotState)>,
+    mut table: ResMut<ReservationTable>
+) {
+    let mut blocked = HashSet::new();
+    let mut opened = false;
+    for change in tile_changes.read().filter(|change| change.walkability_changed()) {
+        if change.new.is_walkable() {opened = true;} else {blocked.insert(change.pos);}
+    }
+    if blocked.is_empty() && !opened {return;}
+
+    for (entity, robot, mut follower, brain_state) in robot_query.iter_mut() {
+        let replan = match brain_state {
+            RobotState::Running => follower.path.iter().chain(follower.plan.iter()).any(|cell| blocked.contains(cell)),
+            RobotState::Stuck => opened && !follower.targets.is_empty(),
+            _ => false
+        };
+        if !replan {continue;}
+
+        follower.path.clear();
+        follower.plan.clear();
+        table.release(entity);
+        commands.entity(entity).insert(PathRequest {
+            start: robot.location,
+            targets: follower.targets.clone()
+        });
+    }
+}
+
+/// Keeps the occupant layer of the grid up to date with where the robots are
+pub fn track_occupants(
+    mut grid: ResMut<Grid>,
+    robot_query: Query<(Entity, &Robot), Changed<Robot>>,
+    mut removed: RemovedComponents<Robot>,
+    mut last_seen: Local<HashMap<Entity, IVec2>>
+) {
+    for entity in removed.read() {
+        let Some(pos) = last_seen.remove(&entity) else {continue};
+        if grid[pos].occupant == Some(entity) {grid.set_occupant(pos, None);}
+    }
+    for (entity, robot) in robot_query.iter() {
+        if let Some(pos) = last_seen.insert(entity, robot.location) {
+            if pos == robot.location {continue;}
+            if grid[pos].occupant == Some(entity) {grid.set_occupant(pos, None);}
+        }
+        grid.set_occupant(robot.location, Some(entity));
+    }
+}
@@ -0,0 +1,558 @@
+use bevy::{ecs::system::CommandQueue, prelude::*, utils::HashMap};
+use ron::ser::{to_string_pretty, PrettyConfig};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::{
+    asset_loading::{BuildingAtlasHandle, BuildingBindings, DoorAtlasHandle, RobotAtlasHandle, WallAtlasHandles},
+    building::{spawn_building, Building, Rotation},
+    doors::{spawn_door, Door},
+    grid::{Fog, Grid, GridEntity, GridLayout, GridScale},
+    history::History,
+    item::{Inventory, Item},
+    reservations::ReservationTable,
+    robot::{spawn_robot_at, Robot, RobotState},
+    script::{Command, RobotScript},
+    walls::{spawn_wall, HitPoints, WallKind, WallTypes},
+    mining::{Miner, ResourceNode},
+    world_gen::{spawn_ore, spawn_rock, Terrain},
+    AppState
+};
+
+/// bumped whenever the save format changes in a way old files can't be read
+pub const SAVE_VERSION: u32 = 1;
+const SAVE_PATH: &str = "saves/world.ron";
+
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, (save_world_on_key, load_world_on_key).run_if(in_state(AppState::Finished)));
+    }
+}
+
+
+/// Everything needed to rebuild the world. Grid tiles aren't stored as they follow from the walls and buildings
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WorldSave {
+    pub version: u32,
+    pub centre: [f32; 2],
+    pub tile_size: f32,
+    pub walls: Vec<[i32; 2]>,
+    pub buildings: Vec<BuildingSave>,
+    pub robots: Vec<RobotSave>,
+    #[serde(default)]
+    pub ore_deposits: Vec<[i32; 2]>,
+    #[serde(default)]
+    pub rocks: Vec<[i32; 2]>,
+    /// ore left in deposits that can run out, any deposit not listed never does
+    #[serde(default)]
+    pub ore_yields: Vec<([i32; 2], u32)>,
+    /// bottom left and top right tiles of the map, missing for saves of maps without edges
+    #[serde(default)]
+    pub bounds: Option<([i32; 2], [i32; 2])>,
+    #[serde(default)]
+    pub layout: GridLayout,
+    /// every tile a robot has seen
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub explored: Vec<[i32; 2]>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub doors: Vec<DoorSave>,
+    /// the type of each wall that isn't of the default type
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wall_types: Vec<([i32; 2], String)>,
+    /// hit points of each wall that has taken damage, the rest are at their type's full hit points
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wall_hit_points: Vec<([i32; 2], u32)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DoorSave {
+    pub location: [i32; 2],
+    pub admits_robots: bool,
+    pub locked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BuildingSave {
+    /// name of the building binding it was built from
+    pub name: String,
+    pub location: [i32; 2],
+    /// missing from saves made before buildings could be rotated
+    #[serde(default)]
+    pub rotation: Rotation,
+    /// what it is holding, such as the ore in a miner
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inventory: Vec<(Item, u32)>,
+    /// how much of its next ore a miner has dug, left out once it is back at the start
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RobotSave {
+    pub location: [i32; 2],
+    pub inventory: Vec<(Item, u32)>,
+    pub script: Option<ScriptSave>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScriptSave {
+    pub commands: Vec<Command>,
+    /// building ids used by the script, paired with an index into the saved buildings
+    pub buildings: Vec<(u32, usize)>,
+    pub items: Vec<(u32, Item)>,
+    pub step: usize,
+}
+
+
+/// Collects the current world into a save. Everything is sorted so the same world always saves the same way
+pub fn capture_world(world: &mut World) -> WorldSave {
+    let grid = world.resource::<Grid>();
+    let (centre, tile_size) = (grid.centre.to_array(), grid.tile_size);
+    let bounds = grid.bounds().map(|(min, max)| (min.to_array(), max.to_array()));
+    let layout = grid.layout;
+    let mut explored: Vec<[i32; 2]> = grid.explored().map(|pos| pos.to_array()).collect();
+    explored.sort();
+
+    let wall_types = world.resource::<WallTypes>().clone();
+    let mut walls = Vec::new();
+    let mut typed_walls = Vec::new();
+    let mut wall_hit_points = Vec::new();
+    for (grid_entity, kind, hit_points) in world.query::<(&GridEntity, &WallKind, &HitPoints)>().iter(world) {
+        walls.push(grid_entity.min.to_array());
+        if kind.0 != 0 {typed_walls.push((grid_entity.min.to_array(), wall_types.0[kind.0].name.clone()));}
+        if hit_points.0 != wall_types.0[kind.0].hit_points {wall_hit_points.push((grid_entity.min.to_array(), hit_points.0));}
+    }
+    walls.sort();
+    typed_walls.sort();
+    wall_hit_points.sort();
+
+    let mut doors: Vec<DoorSave> = world.query::<(&GridEntity, &Door)>().iter(world)
+        .map(|(grid_entity, door)| DoorSave {
+            location: grid_entity.min.to_array(),
+            admits_robots: door.admits_robots,
+            locked: door.locked
+        })
+        .collect();
+    doors.sort_by_key(|door| door.location);
+
+    let (mut ore_deposits, mut rocks, mut ore_yields) = (Vec::new(), Vec::new(), Vec::new());
+    // deposits are told apart by their node rather than the grid, as a miner may be covering them
+    for (terrain, node) in world.query::<(&Terrain, Option<&ResourceNode>)>().iter(world) {
+        match node {
+            Some(node) => {
+                ore_deposits.push(terrain.pos.to_array());
+                if let Some(remaining) = node.remaining {ore_yields.push((terrain.pos.to_array(), remaining));}
+            },
+            None => rocks.push(terrain.pos.to_array())
+        }
+    }
+    ore_deposits.sort();
+    rocks.sort();
+    ore_yields.sort();
+
+    let mut buildings: Vec<(Entity, BuildingSave)> = world.query::<(Entity, &GridEntity, &Building, Option<&Inventory>, Option<&Miner>)>().iter(world)
+        .map(|(entity, grid_entity, building, inventory, miner)| (entity, BuildingSave {
+            name: building.name.clone(),
+            location: grid_entity.min.to_array(),
+            rotation: building.rotation,
+            inventory: inventory.map(sorted_items).unwrap_or_default(),
+            progress: miner.map(|miner| miner.progress).filter(|progress| *progress > 0.0)
+        }))
+        .collect();
+    buildings.sort_by_key(|(_, building)| building.location);
+    let building_indices: HashMap<Entity, usize> = buildings.iter().enumerate().map(|(i, (entity, _))| (*entity, i)).collect();
+
+    let mut robots: Vec<RobotSave> = world.query::<(&Robot, Option<&Inventory>, Option<&RobotScript>)>().iter(world)
+        .map(|(robot, inventory, script)| {
+            RobotSave {
+                location: robot.location.to_array(),
+                inventory: inventory.map(sorted_items).unwrap_or_default(),
+                script: script.map(|script| save_script(script, &building_indices))
+            }
+        })
+        .collect();
+    robots.sort_by_key(|robot| robot.location);
+
+    WorldSave {
+        version: SAVE_VERSION,
+        centre,
+        tile_size,
+        walls,
+        buildings: buildings.into_iter().map(|(_, building)| building).collect(),
+        robots,
+        ore_deposits,
+        rocks,
+        ore_yields,
+        bounds,
+        layout,
+        explored,
+        doors,
+        wall_types: typed_walls,
+        wall_hit_points
+    }
+}
+
+fn sorted_items(inventory: &Inventory) -> Vec<(Item, u32)> {
+    let mut items: Vec<(Item, u32)> = inventory.items.iter().map(|(item, amount)| (*item, *amount)).collect();
+    items.sort();
+    items
+}
+
+fn to_inventory(items: &[(Item, u32)]) -> Inventory {
+    let mut inventory = Inventory::default();
+    for (item, amount) in items {
+        inventory.add(*item, *amount);
+    }
+    inventory
+}
+
+fn save_script(script: &RobotScript, building_indices: &HashMap<Entity, usize>) -> ScriptSave {
+    // references to buildings that have since been deleted are dropped, the script already skips them
+    let mut buildings: Vec<(u32, usize)> = script.buildings.iter()
+        .filter_map(|(id, entity)| building_indices.get(entity).map(|index| (*id, *index)))
+        .collect();
+    buildings.sort();
+    let mut items: Vec<(u32, Item)> = script.items.iter().map(|(id, item)| (*id, *item)).collect();
+    items.sort();
+
+    ScriptSave {
+        commands: script.commands.clone(),
+        buildings,
+        items,
+        step: script.step
+    }
+}
+
+/// Replaces every wall, building and robot with the ones in the save. A save that can't be loaded, including one
+/// made with a different map layout or tile size, is turned away without touching the world
+pub fn restore_world(world: &mut World, save: &WorldSave) -> Result<(), String> {
+    if save.version != SAVE_VERSION {
+        return Err(format!("save is version {} but only version {} can be loaded", save.version, SAVE_VERSION));
+    }
+    // the wall sheets are made for the layout the game started with, and sprites are scaled to its tile size
+    let grid = world.resource::<Grid>();
+    if save.layout != grid.layout {
+        return Err(format!("save is of a {:?} map but this game is {:?}", save.layout, grid.layout));
+    }
+    if save.tile_size != grid.tile_size {
+        return Err(format!("save has tiles {} across but this game has {}", save.tile_size, grid.tile_size));
+    }
+
+    // a dry run into an empty world first, as anything spawned before a mistake is found can't be taken back
+    let mut scratch = World::new();
+    let mut queue = CommandQueue::default();
+    let checked = spawn_save(&mut Commands::new(&mut queue, &scratch), world, save);
+    queue.apply(&mut scratch);
+    checked?;
+
+    let old: Vec<Entity> = world.query_filtered::<Entity, Or<(With<GridEntity>, With<Robot>, With<Terrain>)>>().iter(world).collect();
+    let mut queue = CommandQueue::default();
+    let mut commands = Commands::new(&mut queue, world);
+    for entity in old {
+        commands.entity(entity).despawn_recursive();
+    }
+    let grid = spawn_save(&mut commands, world, save)?;
+    queue.apply(world);
+
+    world.resource_mut::<Grid>().replace_with(grid);
+    if let Some(mut table) = world.get_resource_mut::<ReservationTable>() {
+        *table = ReservationTable::default();
+    }
+    // the history refers to things by where they were, which means nothing in the new world
+    if let Some(mut history) = world.get_resource_mut::<History>() {
+        history.clear();
+    }
+    Ok(())
+}
+
+/// spawns everything in the save onto a new grid, failing on the first thing that doesn't fit
+fn spawn_save(commands: &mut Commands, world: &World, save: &WorldSave) -> Result<Grid, String> {
+    let mut grid = Grid::new(Vec2::from_array(save.centre), save.tile_size).with_layout(save.layout);
+    if let Some((min, max)) = save.bounds {
+        if IVec2::from_array(min).cmpgt(IVec2::from_array(max)).any() {return Err(format!("bounds {:?} to {:?} are back to front", min, max));}
+        grid = grid.with_bounds(IVec2::from_array(min), IVec2::from_array(max));
+    }
+    // the robots see what is around them again once they are back
+    for cell in save.explored.iter() {
+        grid.set_fog(IVec2::from_array(*cell), Fog::Explored);
+    }
+
+    let ore_yields: HashMap<[i32; 2], u32> = save.ore_yields.iter().copied().collect();
+    for cell in save.ore_deposits.iter() {
+        spawn_ore(commands, &mut grid, IVec2::from_array(*cell), ore_yields.get(cell).copied())
+            .ok_or_else(|| format!("ore at {:?} overlaps something", cell))?;
+    }
+    for cell in save.rocks.iter() {
+        spawn_rock(commands, &mut grid, IVec2::from_array(*cell))
+            .ok_or_else(|| format!("rock at {:?} overlaps something", cell))?;
+    }
+
+    let grid_scale = world.resource::<GridScale>();
+    let wall_types = world.resource::<WallTypes>();
+    let wall_atlases = world.resource::<WallAtlasHandles>();
+    let kinds: HashMap<[i32; 2], &String> = save.wall_types.iter().map(|(cell, name)| (*cell, name)).collect();
+    let hit_points: HashMap<[i32; 2], u32> = save.wall_hit_points.iter().copied().collect();
+    for wall in save.walls.iter() {
+        let kind = match kinds.get(wall) {
+            Some(name) => wall_types.by_name(name).ok_or_else(|| format!("unknown wall type {}", name))?,
+            None => 0,
+        };
+        let entity = spawn_wall(commands, &mut grid, grid_scale, IVec2::from_array(*wall), kind, wall_types, wall_atlases)
+            .ok_or_else(|| format!("wall at {:?} overlaps something", wall))?;
+        if let Some(hp) = hit_points.get(wall) {
+            if *hp > wall_types.0[kind].hit_points {return Err(format!("wall at {:?} has more than full hit points", wall));}
+            commands.entity(entity).insert(HitPoints(*hp));
+        }
+    }
+    let door_atlas = world.resource::<DoorAtlasHandle>();
+    for door in save.doors.iter() {
+        let config = Door {admits_robots: door.admits_robots, locked: door.locked};
+        spawn_door(commands, &mut grid, grid_scale, IVec2::from_array(door.location), config, door_atlas)
+            .ok_or_else(|| format!("door at {:?} overlaps something", door.location))?;
+    }
+
+    let bindings = world.resource::<BuildingBindings>();
+    let building_atlas = world.resource::<BuildingAtlasHandle>();
+    let mut buildings = Vec::new();
+    for building in save.buildings.iter() {
+        let spawn_info = bindings.by_name(&building.name).ok_or_else(|| format!("unknown building {}", building.name))?;
+        let entity = spawn_building(commands, &mut grid, grid_scale, IVec2::from_array(building.location), building.rotation, spawn_info, building_atlas)
+            .ok_or_else(|| format!("{} at {:?} overlaps something", building.name, building.location))?;
+        if !building.inventory.is_empty() {
+            commands.entity(entity).insert(to_inventory(&building.inventory));
+        }
+        if let Some(progress) = building.progress.filter(|_| spawn_info.requires_node) {
+            commands.entity(entity).insert(Miner {nodes: Vec::new(), progress});
+        }
+        buildings.push(entity);
+    }
+
+    let robot_atlas = world.resource::<RobotAtlasHandle>();
+    for robot in save.robots.iter() {
+        let location = IVec2::from_array(robot.location);
+        if !grid.is_walkable(location) {return Err(format!("robot at {:?} is stuck in something", robot.location));}
+        let entity = spawn_robot_at(commands, &grid, grid_scale, location, robot_atlas);
+        commands.entity(entity).insert(to_inventory(&robot.inventory));
+
+        let Some(script) = robot.script.as_ref() else {continue};
+        let mut building_bindings = HashMap::new();
+        for (id, index) in script.buildings.iter() {
+            let entity = buildings.get(*index).ok_or_else(|| format!("script refers to building {} of {}", index, buildings.len()))?;
+            building_bindings.insert(*id, *entity);
+        }
+        commands.entity(entity).insert((
+            RobotScript {
+                commands: script.commands.clone(),
+                buildings: building_bindings,
+                items: script.items.iter().copied().collect(),
+                step: script.step
+            },
+            RobotState::Idle
+        ));
+    }
+    Ok(grid)
+}
+
+
+pub fn save_world_on_key(world: &mut World) {
+    if !world.resource::<Input<KeyCode>>().just_pressed(KeyCode::F5) {return;}
+
+    let save = capture_world(world);
+    let result = to_string_pretty(&save, PrettyConfig::default()).map_err(|e| e.to_string())
+        .and_then(|text| {
+            fs::create_dir_all("saves").map_err(|e| e.to_string())?;
+            fs::write(SAVE_PATH, text).map_err(|e| e.to_string())
+        });
+    match result {
+        Ok(()) => info!("Saved world to {}", SAVE_PATH),
+        Err(e) => warn!("Failed to save world: {}", e)
+    }
+}
+
+pub fn load_world_on_key(world: &mut World) {
+    if !world.resource::<Input<KeyCode>>().just_pressed(KeyCode::F9) {return;}
+
+    let result = fs::read_to_string(SAVE_PATH).map_err(|e| e.to_string())
+        .and_then(|text| ron::from_str::<WorldSave>(&text).map_err(|e| e.to_string()))
+        .and_then(|save| restore_world(world, &save));
+    match result {
+        Ok(()) => info!("Loaded world from {}", SAVE_PATH),
+        Err(e) => warn!("Failed to load world: {}", e)
+    }
+}
+
+
+#[cfg(test)]
+mod save_tests {
+    use super::{capture_world, restore_world, BuildingSave, DoorSave, RobotSave, ScriptSave, WorldSave, SAVE_VERSION};
+    use crate::{
+        asset_loading::{BuildingAtlasHandle, BuildingBindings, BuildingSpawnInfo, DoorAtlasHandle, RobotAtlasHandle, WallAtlasHandles},
+        building::Rotation,
+        grid::{Floor, Grid, GridLayout, GridScale, Object, Tile},
+        item::Item,
+        script::Command,
+        walls::{WallTypeInfo, WallTypes}
+    };
+    use bevy::{prelude::*, utils::HashMap};
+
+    fn test_world() -> World {
+        let mut world = World::new();
+        world.insert_resource(Grid::new(Vec2::ZERO, 25.0));
+        world.insert_resource(GridScale(Vec3::splat(0.5)));
+        let stone = WallTypes::default().0.remove(0);
+        let brick = WallTypeInfo {name: "Brick".to_string(), hit_points: 250, ..stone.clone()};
+        world.insert_resource(WallTypes(vec![stone, brick]));
+        world.insert_resource(WallAtlasHandles(vec![Handle::default(); 2]));
+        world.insert_resource(BuildingAtlasHandle(Handle::default()));
+        world.insert_resource(RobotAtlasHandle(Handle::default()));
+        world.insert_resource(DoorAtlasHandle(Handle::default()));
+        world.insert_resource(BuildingBindings(HashMap::from([(0, BuildingSpawnInfo {
+            sprite_index: 0,
+            name: "Smelter".to_string(),
+            max_offset: IVec2::new(2, 1),
+            interaction_points: vec![IVec2::new(-1, 0)],
+            requires_node: false
+        }), (1, BuildingSpawnInfo {
+            sprite_index: 1,
+            name: "Miner".to_string(),
+            max_offset: IVec2::ZERO,
+            interaction_points: vec![],
+            requires_node: true
+        })])));
+        world
+    }
+
+    fn example_save() -> WorldSave {
+        WorldSave {
+            version: SAVE_VERSION,
+            centre: [0.0, 0.0],
+            tile_size: 25.0,
+            walls: vec![[-3, 0], [-3, 1], [5, 5]],
+            ore_deposits: vec![[-6, -6], [-6, -5]],
+            rocks: vec![[8, 8]],
+            ore_yields: vec![([-6, -6], 0), ([-6, -5], 12)],
+            bounds: Some(([-20, -20], [19, 19])),
+            layout: GridLayout::Square,
+            explored: vec![[0, 0], [0, 1], [1, 0]],
+            doors: vec![
+                DoorSave {location: [-3, 2], admits_robots: true, locked: false},
+                DoorSave {location: [-3, 3], admits_robots: true, locked: true},
+            ],
+            wall_types: vec![([-3, 0], "Brick".to_string())],
+            wall_hit_points: vec![([-3, 0], 180), ([5, 5], 40)],
+            buildings: vec![
+                BuildingSave {name: "Miner".to_string(), location: [-6, -5], rotation: Rotation::R0, inventory: vec![(Item::Ore, 2)], progress: Some(0.25)},
+                BuildingSave {name: "Smelter".to_string(), location: [0, 0], rotation: Rotation::R0, inventory: vec![(Item::Ore, 3)], progress: None},
+                BuildingSave {name: "Smelter".to_string(), location: [4, -2], rotation: Rotation::R90, inventory: vec![], progress: None},
+            ],
+            robots: vec![
+                RobotSave {location: [-1, 3], inventory: vec![], script: None},
+                RobotSave {
+                    location: [2, 2],
+                    inventory: vec![(Item::Ore, 4), (Item::Gear, 1)],
+                    script: Some(ScriptSave {
+                        commands: vec![Command::Goto(7), Command::Take(0, 2), Command::Goto(8), Command::PrintInventory],
+                        buildings: vec![(7, 2), (8, 1)],
+                        items: vec![(0, Item::Bar)],
+                        step: 2
+                    })
+                },
+            ]
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let save = example_save();
+        let mut world = test_world();
+        restore_world(&mut world, &save).unwrap();
+
+        let grid = world.resource::<Grid>();
+        assert_eq!(grid[IVec2::new(-3, 1)].object, Some(Object::Wall));
+        assert_eq!(grid[IVec2::new(5, -1)].object, Some(Object::Building));
+        // the second smelter is turned a quarter, so its interaction point has moved below it
+        assert!(grid[IVec2::new(5, -3)].is_interaction_point());
+        assert_eq!(grid[IVec2::new(6, -2)], Tile::default());
+        assert_eq!(grid[IVec2::new(3, 0)], Tile::default());
+        assert_eq!(grid[IVec2::new(-6, -6)].floor, Floor::DepletedOre);
+        assert_eq!(grid.owner(IVec2::new(5, -1)), grid.owner(IVec2::new(4, -2)));
+        assert!(grid.owner(IVec2::new(5, 5)).is_some());
+        assert_eq!(grid.owner(IVec2::new(5, -3)), None);
+        assert!(grid.is_explored(IVec2::new(0, 1)) && !grid.is_explored(IVec2::new(1, 1)));
+        assert!(grid.is_walkable(IVec2::new(-3, 2)) && !grid.is_walkable(IVec2::new(-3, 3)));
+
+        assert_eq!(capture_world(&mut world), save);
+
+        let text = ron::to_string(&save).unwrap();
+        assert_eq!(ron::from_str::<WorldSave>(&text).unwrap(), save);
+    }
+
+    #[test]
+    fn loading_replaces_the_world() {
+        let mut world = test_world();
+        restore_world(&mut world, &example_save()).unwrap();
+        let mut smaller = example_save();
+        smaller.walls.truncate(1);
+        smaller.wall_hit_points.truncate(1);
+        smaller.robots.clear();
+        restore_world(&mut world, &smaller).unwrap();
+
+        assert_eq!(capture_world(&mut world), smaller);
+        assert_eq!(world.resource::<Grid>()[IVec2::new(5, 5)], Tile::default());
+    }
+
+    #[test]
+    fn bad_saves() {
+        let mut world = test_world();
+        restore_world(&mut world, &example_save()).unwrap();
+        let entities = world.entities().len();
+        let mut save = example_save();
+        save.version = SAVE_VERSION + 1;
+        assert!(restore_world(&mut world, &save).is_err());
+
+        let mut save = example_save();
+        save.buildings[0].name = "Teleporter".to_string();
+        assert!(restore_world(&mut world, &save).is_err());
+
+        // off the edge of the map
+        let mut save = example_save();
+        save.walls.push([25, 0]);
+        assert!(restore_world(&mut world, &save).is_err());
+
+        let mut save = example_save();
+        save.wall_types[0].1 = "Glass".to_string();
+        assert!(restore_world(&mut world, &save).is_err());
+
+        let mut save = example_save();
+        save.wall_hit_points[1].1 = 101;
+        assert!(restore_world(&mut world, &save).is_err());
+
+        let mut save = example_save();
+        save.robots[0].location = [-3, 0];
+        assert!(restore_world(&mut world, &save).is_err());
+
+        // none of them left anything behind
+        assert_eq!(world.entities().len(), entities);
+        assert_eq!(capture_world(&mut world), example_save());
+    }
+
+    #[test]
+    fn other_map_configs() {
+        let mut world = test_world();
+        restore_world(&mut world, &example_save()).unwrap();
+        let mut hex = example_save();
+        hex.layout = GridLayout::Hex;
+        let text = ron::to_string(&hex).unwrap();
+        assert!(restore_world(&mut world, &ron::from_str(&text).unwrap()).is_err());
+
+        let mut bigger = example_save();
+        bigger.tile_size = 40.0;
+        let text = ron::to_string(&bigger).unwrap();
+        assert!(restore_world(&mut world, &ron::from_str(&text).unwrap()).is_err());
+        assert_eq!(capture_world(&mut world), example_save());
+    }
+}
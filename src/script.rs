@@ -1,8 +1,18 @@
 use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
 
-use crate::item::Item;
+use crate::{building::BuildingTag, connectivity::ConnectivityRegions, doors::{set_door_locked, Door}, fog::nearest_frontier, grid::{Grid, GridEntity}, item::Item, path_tasks::PathRequest, robot::{Robot, RobotState}, AppState};
 
 
+pub struct ScriptPlugin;
+
+impl Plugin for ScriptPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, (attach_tour_script, attach_explore_script, run_robot_scripts).run_if(in_state(AppState::Finished)));
+    }
+}
+
 
 pub struct ScriptBuilder {
     pub commands: Vec<Command>
@@ -17,6 +27,7 @@ pub fn build_script(builder: ScriptBuilder, building_bindings: HashMap<u32, Enti
     }
 }
 
+#[derive(Component)]
 pub struct RobotScript {
     pub commands: Vec<Command>,
     pub buildings: HashMap<u32, Entity>,
@@ -24,10 +35,185 @@ pub struct RobotScript {
     pub step: usize,
 }
 
-#[derive(Clone)]
+impl RobotScript {
+    /// moves on to the next command, scripts loop back to the start once finished
+    pub fn advance(&mut self) {
+        self.step = (self.step + 1) % self.commands.len();
+    }
+}
+
+/// Checks a script before a robot starting at `start` runs it, returning a warning for each problem found
+pub fn validate_script(script: &RobotScript, start: IVec2, regions: &ConnectivityRegions, grid_entity_query: &Query<&GridEntity>) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for (step, command) in script.commands.iter().enumerate() {
+        let Command::Goto(building_id) = command else {continue};
+        let Some(grid_entity) = script.buildings.get(building_id).and_then(|building| grid_entity_query.get(*building).ok()) else {
+            warnings.push(format!("step {}: building {} does not exist", step, building_id));
+            continue;
+        };
+        if !grid_entity.interaction_points.iter().any(|point| regions.is_reachable(start, *point)) {
+            warnings.push(format!("step {}: building {} at {} cannot be reached from {}", step, building_id, grid_entity.min, start));
+        }
+    }
+    warnings
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Command {
     Goto(u32), // Building ID
     Give(u32, u32), // Item ID, amount
     Take(u32, u32), // Item ID, amount
-    PrintInventory
-}
\ No newline at end of file
+    PrintInventory,
+    /// head for the nearest edge of the explored area
+    Explore,
+    Lock(i32, i32), // Door x, y
+    Unlock(i32, i32) // Door x, y
+}
+
+
+pub fn run_robot_scripts(
+    mut commands: Commands,
+    mut robot_query: Query<(Entity, &Robot, &RobotState, &mut RobotScript)>,
+    other_robots: Query<&Robot>,
+    grid_entity_query: Query<&GridEntity>,
+    mut door_query: Query<&mut Door>,
+    grid: Res<Grid>,
+    regions: Res<ConnectivityRegions>
+) {
+    for (entity, robot, brain_state, mut script) in robot_query.iter_mut() {
+        if !matches!(brain_state, RobotState::Idle) || script.commands.is_empty() {continue;}
+
+        match script.commands[script.step] {
+            Command::Goto(building_id) => {
+                let Some(grid_entity) = script.buildings.get(&building_id).and_then(|building| grid_entity_query.get(*building).ok()) else {
+                    warn!("Script refers to building {} which does not exist", building_id);
+                    script.advance();
+                    continue;
+                };
+                if grid_entity.interaction_points.contains(&robot.location) {
+                    script.advance();
+                    continue;
+                }
+
+                let reachable: Vec<IVec2> = grid_entity.interaction_points.iter().copied()
+                    .filter(|cell| regions.is_reachable(robot.location, *cell))
+                    .collect();
+                // walled off, the validator will already have warned about it
+                if reachable.is_empty() {
+                    script.advance();
+                    continue;
+                }
+
+                let targets: Vec<IVec2> = reachable.into_iter()
+                    .filter(|cell| grid.is_walkable(*cell) && !other_robots.iter().any(|other| other.location == *cell))
+                    .collect();
+                // every interaction point is taken, wait for one to free up
+                if targets.is_empty() {continue;}
+
+                commands.entity(entity).insert(PathRequest {
+                    start: robot.location,
+                    targets
+                });
+            },
+            Command::Explore => {
+                // the next command only runs once the robot is idle again, so there is no need to wait for it here
+                script.advance();
+                // nothing will turn up by searching again every frame, so the robot stops for good
+                let Some(frontier) = nearest_frontier(&grid, robot.location) else {
+                    info!("Robot at {} has nothing left to explore", robot.location);
+                    commands.entity(entity).remove::<RobotScript>();
+                    continue;
+                };
+                commands.entity(entity).insert(PathRequest {
+                    start: robot.location,
+                    targets: vec![frontier]
+                });
+            },
+            Command::Lock(x, y) | Command::Unlock(x, y) => {
+                let locked = matches!(script.commands[script.step], Command::Lock(..));
+                if !set_door_locked(&grid, &mut door_query, IVec2::new(x, y), locked) {
+                    warn!("Script refers to a door at ({}, {}) which does not exist", x, y);
+                }
+                script.advance();
+            },
+            _ => script.advance()
+        }
+    }
+}
+
+/// gives every robot a script that visits each building in turn
+pub fn attach_tour_script(
+    keyboard: Res<Input<KeyCode>>,
+    mut commands: Commands,
+    mut robot_query: Query<(Entity, &Robot, &mut RobotState)>,
+    building_query: Query<Entity, With<BuildingTag>>,
+    grid_entity_query: Query<&GridEntity>,
+    regions: Res<ConnectivityRegions>
+) {
+    if !keyboard.just_pressed(KeyCode::T) {return;}
+
+    let buildings: HashMap<u32, Entity> = building_query.iter().enumerate().map(|(i, entity)| (i as u32, entity)).collect();
+    if buildings.is_empty() {return;}
+    let builder = ScriptBuilder {
+        commands: (0..buildings.len() as u32).map(Command::Goto).collect()
+    };
+
+    for (entity, robot, mut brain_state) in robot_query.iter_mut() {
+        let script = build_script(ScriptBuilder {commands: builder.commands.clone()}, buildings.clone(), HashMap::new());
+        for warning in validate_script(&script, robot.location, &regions, &grid_entity_query) {
+            warn!("Robot {:?}: {}", entity, warning);
+        }
+        commands.entity(entity).insert(script);
+        *brain_state = RobotState::Idle;
+    }
+}
+
+/// gives every robot a script that keeps exploring until there is nothing left to find
+pub fn attach_explore_script(
+    keyboard: Res<Input<KeyCode>>,
+    mut commands: Commands,
+    mut robot_query: Query<(Entity, &mut RobotState), With<Robot>>
+) {
+    if !keyboard.just_pressed(KeyCode::X) {return;}
+
+    for (entity, mut brain_state) in robot_query.iter_mut() {
+        let script = build_script(ScriptBuilder {commands: vec![Command::Explore]}, HashMap::new(), HashMap::new());
+        commands.entity(entity).insert(script);
+        *brain_state = RobotState::Idle;
+    }
+}
+
+
+#[cfg(test)]
+mod script_tests {
+    use super::{build_script, run_robot_scripts, Command, RobotScript, ScriptBuilder};
+    use crate::{connectivity::ConnectivityRegions, grid::{Fog, Grid}, path_tasks::PathRequest, robot::{Robot, RobotState}};
+    use bevy::{prelude::*, utils::HashMap};
+
+    fn explore_once(explored: i32) -> (World, Entity) {
+        let mut world = World::new();
+        let mut grid = Grid::new(Vec2::ZERO, 1.0).with_bounds(IVec2::splat(-3), IVec2::splat(3));
+        for x in -explored..=explored {
+            for y in -3..=3 {
+                grid.set_fog(IVec2::new(x, y), Fog::Explored);
+            }
+        }
+        world.insert_resource(grid);
+        world.insert_resource(ConnectivityRegions::default());
+        let script = build_script(ScriptBuilder {commands: vec![Command::Explore]}, HashMap::new(), HashMap::new());
+        let robot = world.spawn((Robot {location: IVec2::ZERO}, RobotState::Idle, script)).id();
+        Schedule::default().add_systems(run_robot_scripts).run(&mut world);
+        (world, robot)
+    }
+
+    #[test]
+    fn explore_stops_when_done() {
+        let (world, robot) = explore_once(1);
+        assert_eq!(world.get::<PathRequest>(robot).unwrap().targets, vec![IVec2::new(1, 0)]);
+        assert!(world.get::<RobotScript>(robot).is_some());
+
+        // the whole map has been seen
+        let (world, robot) = explore_once(3);
+        assert!(world.get::<PathRequest>(robot).is_none() && world.get::<RobotScript>(robot).is_none());
+    }
+}
@@ -0,0 +1,246 @@
+use bevy::prelude::*;
+
+use crate::{
+    blueprint::BlueprintTool,
+    grid::{grid_to_space, space_to_grid, Grid, GridLayout},
+    history::{Action, GridEditor, History, Placed},
+    interaction::{InteractionSpriteIndices, TileSelectIndicator},
+    asset_loading::StepableAnimation,
+    walls::WallTypes,
+    AppState
+};
+
+const PREVIEW_COLOUR: Color = Color::rgba(0.2, 1.0, 0.3, 0.4);
+const BLOCKED_PREVIEW_COLOUR: Color = Color::rgba(1.0, 0.2, 0.2, 0.4);
+
+
+pub struct WallDrawingPlugin;
+
+impl Plugin for WallDrawingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<WallDrawing>()
+            .add_systems(Update, (cycle_wall_draw_mode, draw_walls, draw_wall_preview)
+                .chain()
+                .run_if(in_state(AppState::Finished)));
+    }
+}
+
+
+/// The shape walls are drawn in between where the mouse was pressed and where it is let go, changed with Q
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WallDrawMode {
+    #[default]
+    Line,
+    Rectangle,
+    FilledRectangle,
+}
+
+impl WallDrawMode {
+    pub fn next(&self) -> Self {
+        match self {
+            WallDrawMode::Line => WallDrawMode::Rectangle,
+            WallDrawMode::Rectangle => WallDrawMode::FilledRectangle,
+            WallDrawMode::FilledRectangle => WallDrawMode::Line,
+        }
+    }
+
+    /// the tiles covered going from `start` to `end`, each once
+    pub fn cells(&self, grid: &Grid, start: IVec2, end: IVec2) -> Vec<IVec2> {
+        match self {
+            WallDrawMode::Line if grid.layout == GridLayout::Hex => hex_line(grid, start, end),
+            WallDrawMode::Line => line(start, end),
+            WallDrawMode::Rectangle => rectangle(start, end, false),
+            WallDrawMode::FilledRectangle => rectangle(start, end, true),
+        }
+    }
+}
+
+/// Bresenham's line, every step moves to one of the eight tiles around the last so there are no gaps
+pub fn line(start: IVec2, end: IVec2) -> Vec<IVec2> {
+    let delta = (end - start).abs();
+    let step = (end - start).signum();
+    let mut error = delta.x - delta.y;
+    let mut pos = start;
+    let mut cells = vec![pos];
+    while pos != end {
+        let doubled = error * 2;
+        if doubled > -delta.y {
+            error -= delta.y;
+            pos.x += step.x;
+        }
+        if doubled < delta.x {
+            error += delta.x;
+            pos.y += step.y;
+        }
+        cells.push(pos);
+    }
+    cells
+}
+
+/// A line of hexes, found by stepping along the straight line between their centres. It is nudged off the
+/// centre line a little, so it doesn't waver when passing exactly between two hexes
+pub fn hex_line(grid: &Grid, start: IVec2, end: IVec2) -> Vec<IVec2> {
+    let steps = grid.layout.distance(start, end).max(1);
+    let nudge = Vec2::splat(grid.tile_size * 0.001);
+    let (from, to) = (grid_to_space(start, grid) + nudge, grid_to_space(end, grid) + nudge);
+    let mut cells: Vec<IVec2> = (0..=steps)
+        .map(|i| space_to_grid(from.lerp(to, i as f32 / steps as f32).extend(0.0), grid))
+        .collect();
+    cells.dedup();
+    cells
+}
+
+/// the tiles of the rectangle with `start` and `end` as opposite corners, just its edges unless filled
+pub fn rectangle(start: IVec2, end: IVec2, filled: bool) -> Vec<IVec2> {
+    let (min, max) = (start.min(end), start.max(end));
+    (min.y..=max.y).flat_map(|y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+        .filter(|cell| filled || cell.x == min.x || cell.x == max.x || cell.y == min.y || cell.y == max.y)
+        .collect()
+}
+
+
+#[derive(Resource, Default)]
+pub struct WallDrawing {
+    pub mode: WallDrawMode,
+    /// where the mouse was pressed, while it is held with a wall selected
+    pub start: Option<IVec2>,
+    /// which of the `WallTypes` was selected when the drag started
+    pub kind: usize,
+}
+
+#[derive(Component)]
+pub struct WallPreviewTag;
+
+pub fn cycle_wall_draw_mode(
+    keyboard: Res<Input<KeyCode>>,
+    mut drawing: ResMut<WallDrawing>
+) {
+    if !keyboard.just_pressed(KeyCode::Q) {return;}
+    drawing.mode = drawing.mode.next();
+    info!("Drawing walls as {:?}", drawing.mode);
+}
+
+/// Starts a drag when the mouse is pressed with a wall selected, and builds every wall of it once let go.
+/// Escape drops the drag without building anything
+#[allow(clippy::too_many_arguments)]
+pub fn draw_walls(
+    mouse: Res<Input<MouseButton>>,
+    keyboard: Res<Input<KeyCode>>,
+    tile_select: Query<&TileSelectIndicator>,
+    info_index: Query<&StepableAnimation>,
+    interaction_indices: Res<InteractionSpriteIndices>,
+    blueprint_tool: Res<BlueprintTool>,
+    wall_types: Res<WallTypes>,
+    mut drawing: ResMut<WallDrawing>,
+    mut history: ResMut<History>,
+    mut editor: GridEditor
+) {
+    let cursor = tile_select.single().pos;
+    let selected = interaction_indices.walls.iter().position(|index| *index == info_index.single().current_index);
+    if let Some(kind) = selected.filter(|_| mouse.just_pressed(MouseButton::Left)) {
+        if !blueprint_tool.is_busy(&keyboard) && !keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+            drawing.start = Some(cursor);
+            drawing.kind = kind;
+        }
+    }
+    if keyboard.just_pressed(KeyCode::Escape) && drawing.start.is_some() {drawing.start = None;}
+    let Some(start) = drawing.start else {return};
+    if mouse.pressed(MouseButton::Left) {return;}
+
+    drawing.start = None;
+    // the whole drag is undone in one go
+    history.begin_stroke();
+    for cell in drawing.mode.cells(editor.grid(), start, cursor) {
+        let placed = Placed::Wall(wall_types.0[drawing.kind].name.clone(), cell);
+        if editor.place(&placed) {history.record(Action::Place(placed));}
+    }
+    history.end_stroke();
+}
+
+/// shows the walls a drag would build, and how many there are and what they cost, only when something has changed
+pub fn draw_wall_preview(
+    mut commands: Commands,
+    drawing: Res<WallDrawing>,
+    tile_select: Query<&TileSelectIndicator>,
+    preview_query: Query<Entity, With<WallPreviewTag>>,
+    wall_types: Res<WallTypes>,
+    grid: Res<Grid>,
+    mut last_cursor: Local<IVec2>
+) {
+    let cursor = tile_select.single().pos;
+    let moved = drawing.start.is_some() && (cursor != *last_cursor || grid.is_changed());
+    if !drawing.is_changed() && !moved {return;}
+    *last_cursor = cursor;
+
+    for entity in preview_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    let Some(start) = drawing.start else {return};
+
+    let info = &wall_types.0[drawing.kind];
+    let mut count = 0;
+    for cell in drawing.mode.cells(&grid, start, cursor) {
+        let buildable = grid.is_free(cell) && grid.is_explored(cell);
+        if buildable {count += 1;}
+        commands.spawn(SpriteBundle {
+            sprite: Sprite {
+                color: if buildable {PREVIEW_COLOUR} else {BLOCKED_PREVIEW_COLOUR},
+                custom_size: Some(Vec2::splat(grid.tile_size)),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(grid_to_space(cell, &grid).extend(0.7)),
+            ..Default::default()
+        })
+        .insert(WallPreviewTag);
+    }
+
+    commands.spawn(Text2dBundle {
+        text: Text::from_section(format!("{:?}: {} {} walls, cost {}", drawing.mode, count, info.name, count * info.cost), TextStyle::default()),
+        transform: Transform::from_translation((grid_to_space(cursor, &grid) + Vec2::new(0.0, grid.tile_size)).extend(0.8)),
+        ..Default::default()
+    })
+    .insert(WallPreviewTag);
+}
+
+
+#[cfg(test)]
+mod wall_drawing_tests {
+    use super::{hex_line, line, rectangle};
+    use crate::grid::{Grid, GridLayout};
+    use bevy::prelude::{IVec2, Vec2};
+
+    #[test]
+    fn lines() {
+        assert_eq!(line(IVec2::new(2, 3), IVec2::new(2, 3)), vec![IVec2::new(2, 3)]);
+        assert_eq!(line(IVec2::ZERO, IVec2::new(3, 3)), (0..=3).map(IVec2::splat).collect::<Vec<_>>());
+        assert_eq!(line(IVec2::ZERO, IVec2::new(-3, 0)), (0..=3).map(|x| IVec2::new(-x, 0)).collect::<Vec<_>>());
+
+        for end in [IVec2::new(7, 2), IVec2::new(-2, 9), IVec2::new(-5, -4), IVec2::new(3, -8)] {
+            let cells = line(IVec2::new(1, 1), end);
+            assert_eq!((cells[0], *cells.last().unwrap()), (IVec2::new(1, 1), end));
+            // no gaps, and as few tiles as possible
+            assert!(cells.windows(2).all(|pair| (pair[1] - pair[0]).abs().max_element() == 1));
+            assert_eq!(cells.len() as i32, (end - IVec2::new(1, 1)).abs().max_element() + 1);
+        }
+    }
+
+    #[test]
+    fn hex_lines() {
+        let grid = Grid::new(Vec2::ZERO, 1.0).with_layout(GridLayout::Hex);
+        for end in [IVec2::new(5, 0), IVec2::new(0, 4), IVec2::new(3, -6), IVec2::new(-4, 2)] {
+            let cells = hex_line(&grid, IVec2::ZERO, end);
+            assert_eq!((cells[0], *cells.last().unwrap()), (IVec2::ZERO, end));
+            assert!(cells.windows(2).all(|pair| grid.layout.distance(pair[0], pair[1]) == 1));
+        }
+    }
+
+    #[test]
+    fn rectangles() {
+        let outline = rectangle(IVec2::new(3, 2), IVec2::new(0, 0), false);
+        assert_eq!(outline.len(), 10);
+        assert!(!outline.contains(&IVec2::new(1, 1)) && outline.contains(&IVec2::new(3, 1)));
+        assert_eq!(rectangle(IVec2::new(3, 2), IVec2::new(0, 0), true).len(), 12);
+        assert_eq!(rectangle(IVec2::ZERO, IVec2::new(0, 4), false).len(), 5);
+    }
+}
@@ -1,25 +1,118 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashSet};
+use serde::Deserialize;
+use std::fs;
 
-use crate::{AppState, grid::{grid_to_space, Grid, TileState, GridEntity, GridScale}, asset_loading::{StateAnimationIndex, WallAtlasHandle}};
+use crate::{AppState, grid::{grid_to_space, Grid, GridLayout, Object, TileChanged, TileChangeFlush, GridEntity, GridScale, HEX_NEIGHBOURS}, asset_loading::{StateAnimationIndex, WallAtlasHandles}};
+
+const WALL_TYPES_PATH: &str = "assets/wall_types.ron";
 
 pub struct WallPlugin;
 
 impl Plugin for WallPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_systems(Update, (update_wall_sprites, update_walls).run_if(in_state(AppState::Finished)));
+            .add_systems(OnEnter(AppState::Setup), load_wall_types)
+            .add_systems(PostUpdate, (update_walls, update_wall_sprites).chain().after(TileChangeFlush).run_if(in_state(AppState::Finished)));
+    }
+}
+
+/// A material walls can be built from, loaded from `assets/wall_types.ron`
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct WallTypeInfo {
+    pub name: String,
+    /// blob tiles for square maps, in `robot_game/sprite_sheets`
+    pub sheet: String,
+    /// tiles for hex maps
+    pub hex_sheet: String,
+    /// icon in the selector, in `robot_game/sprites/selector_images`
+    pub ui_sprite: String,
+    /// shown when drawing walls, there is nothing to pay it with yet
+    pub cost: u32,
+    /// what a new wall starts with
+    pub hit_points: u32,
+    /// walls of a type only join up with their own type, unless both types allow it
+    #[serde(default)]
+    pub connects_across: bool,
+}
+
+/// Every wall type, the first is the default. Walls refer to their type by where it is in here
+#[derive(Resource, Clone, Debug)]
+pub struct WallTypes(pub Vec<WallTypeInfo>);
+
+impl Default for WallTypes {
+    fn default() -> Self {
+        WallTypes(vec![WallTypeInfo {
+            name: "Stone".to_string(),
+            sheet: "blob_walls.png".to_string(),
+            hex_sheet: "hex_walls.png".to_string(),
+            ui_sprite: "Wall.png".to_string(),
+            cost: 1,
+            hit_points: 100,
+            connects_across: false,
+        }])
+    }
+}
+
+impl WallTypes {
+    pub fn by_name(&self, name: &str) -> Option<usize> {
+        self.0.iter().position(|info| info.name == name)
+    }
+
+    /// the type of walls in saves and blueprints that don't say
+    pub fn default_name(&self) -> &str {
+        &self.0[0].name
+    }
+
+    /// whether walls of the two types join up
+    pub fn connects(&self, a: usize, b: usize) -> bool {
+        a == b || (self.0[a].connects_across && self.0[b].connects_across)
     }
 }
 
+pub fn load_wall_types(
+    mut commands: Commands
+) {
+    let wall_types = fs::read_to_string(WALL_TYPES_PATH).map_err(|e| e.to_string())
+        .and_then(|text| ron::from_str::<Vec<WallTypeInfo>>(&text).map_err(|e| e.to_string()))
+        .and_then(|types| if types.is_empty() {Err("no wall types".to_string())} else {Ok(WallTypes(types))})
+        .unwrap_or_else(|e| {
+            warn!("Failed to load {}, using the default wall: {}", WALL_TYPES_PATH, e);
+            WallTypes::default()
+        });
+    commands.insert_resource(wall_types);
+}
+
+/// all eight tiles around a square tile, going anticlockwise from the right. Bit i of a blob mask is the i-th one
+pub const BLOB_NEIGHBOURS: [IVec2; 8] = [
+    IVec2::new(1, 0), IVec2::new(1, 1), IVec2::new(0, 1), IVec2::new(-1, 1),
+    IVec2::new(-1, 0), IVec2::new(-1, -1), IVec2::new(0, -1), IVec2::new(1, -1),
+];
+
+/// Every blob mask once its corners have been dropped (see `reduce_blob_mask`), in the order of the sprites in
+/// `blob_walls.png`, so a sprite's index is where its mask is in this list
+pub const BLOB_MASKS: [u8; 47] = [
+    0, 1, 4, 5, 7, 16, 17, 20, 21, 23, 28, 29, 31, 64, 65, 68, 69, 71, 80, 81, 84, 85, 87, 92,
+    93, 95, 112, 113, 116, 117, 119, 124, 125, 127, 193, 197, 199, 209, 213, 215, 221, 223, 241, 245, 247, 253, 255,
+];
 
 
 #[derive(Component)]
 pub struct WallTag;
 
+/// which of the `WallTypes` a wall is made of
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WallKind(pub usize);
+
+/// how much more damage a wall can take, kept in saves. Nothing deals damage yet
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HitPoints(pub u32);
+
 
 #[derive(Bundle)]
 pub struct WallBundle {
     pub tag: WallTag,
+    pub kind: WallKind,
+    pub hit_points: HitPoints,
     pub state: StateAnimationIndex,
     pub sprite: SpriteSheetBundle,
     pub grid_entity: GridEntity
@@ -28,28 +121,74 @@ pub struct WallBundle {
 
 
 
+#[allow(clippy::type_complexity)]
 pub fn update_wall_sprites(
-    mut wall_query: Query<(&StateAnimationIndex, &mut TextureAtlasSprite), With<WallTag>>
+    mut wall_query: Query<(&StateAnimationIndex, &mut TextureAtlasSprite), (With<WallTag>, Changed<StateAnimationIndex>)>
 ) {
     for (state, mut sprite) in wall_query.iter_mut() {
         sprite.index = state.index;
     }
 }
 
+/// picks the sprite of every wall next to a tile that changed, so it joins up with the doors and walls of its type around it
 pub fn update_walls(
-    mut wall_query: Query<(&GridEntity, &mut StateAnimationIndex), With<WallTag>>,
+    mut wall_query: Query<(&GridEntity, &WallKind, &mut StateAnimationIndex), With<WallTag>>,
+    kind_query: Query<&WallKind>,
+    mut tile_changes: EventReader<TileChanged>,
     grid: Res<Grid>,
+    wall_types: Res<WallTypes>,
 ) {
-    for (grid_entity, mut state) in wall_query.iter_mut() {
+    let affected: HashSet<IVec2> = tile_changes.read()
+        .flat_map(|change| std::iter::once(change.pos).chain(connected_offsets(grid.layout).iter().map(move |offset| change.pos + *offset)))
+        .collect();
+    if affected.is_empty() {return;}
+
+    for (grid_entity, kind, mut state) in wall_query.iter_mut() {
         let index = grid_entity.min;
-        let mut state_index = 0;
+        if !affected.contains(&index) {continue;}
+        let joins = |cell: IVec2| match grid[cell].object {
+            Some(Object::Door {..}) => true,
+            Some(Object::Wall) => grid.owner(cell).and_then(|owner| kind_query.get(owner).ok())
+                .is_some_and(|other| wall_types.connects(kind.0, other.0)),
+            _ => false,
+        };
+        let state_index = wall_index(grid.layout, index, joins);
+        if state.index != state_index {state.index = state_index;}
+    }
+}
 
-        if grid[index + IVec2::X] == TileState::Wall {state_index += 1};
-        if grid[index + IVec2::Y] == TileState::Wall {state_index += 2};
-        if grid[index - IVec2::X] == TileState::Wall {state_index += 4};
-        if grid[index - IVec2::Y] == TileState::Wall {state_index += 8};
+/// the tiles a wall can join up with: diagonals too on squares, so inner corners can be drawn
+fn connected_offsets(layout: GridLayout) -> &'static [IVec2] {
+    match layout {
+        GridLayout::Square => &BLOB_NEIGHBOURS,
+        GridLayout::Hex => &HEX_NEIGHBOURS,
+    }
+}
 
-        state.index = state_index;
+/// A corner only changes how a wall looks when both of the sides next to it are walls as well, otherwise it is dropped
+pub fn reduce_blob_mask(mask: u8) -> u8 {
+    let mut reduced = mask & 0b0101_0101;
+    for corner in [1, 3, 5, 7] {
+        let sides = 1 << (corner - 1) | 1 << ((corner + 1) % 8);
+        if mask & 1 << corner != 0 && mask & sides == sides {reduced |= 1 << corner;}
+    }
+    reduced
+}
+
+/// which of the 47 blob sprites a mask of the eight surrounding walls is drawn with
+pub fn blob_index(mask: u8) -> usize {
+    BLOB_MASKS.binary_search(&reduce_blob_mask(mask)).expect("every reduced mask is in the table")
+}
+
+/// Sprite index for a wall. Bit i of the mask is set when the wall joins the i-th connected tile, hexes use it as is
+/// while squares look it up in the blob table
+pub fn wall_index(layout: GridLayout, pos: IVec2, joins: impl Fn(IVec2) -> bool) -> usize {
+    let mask = connected_offsets(layout).iter().enumerate()
+        .filter(|(_, offset)| joins(pos + **offset))
+        .fold(0, |mask, (i, _)| mask | 1 << i);
+    match layout {
+        GridLayout::Square => blob_index(mask as u8),
+        GridLayout::Hex => mask,
     }
 }
 
@@ -58,15 +197,20 @@ pub fn spawn_wall(
     grid: &mut Grid,
     grid_scale: &GridScale,
     location: IVec2,
-    atlas_handle: &WallAtlasHandle
-) {
-    if grid[location] != TileState::Empty {return}
+    kind: usize,
+    wall_types: &WallTypes,
+    atlas_handles: &WallAtlasHandles
+) -> Option<Entity> {
+    if !grid.is_free(location) {return None}
+    let (info, atlas_handle) = wall_types.0.get(kind).zip(atlas_handles.0.get(kind))?;
 
-    commands.spawn(WallBundle {
+    let entity = commands.spawn(WallBundle {
         tag: WallTag,
+        kind: WallKind(kind),
+        hit_points: HitPoints(info.hit_points),
         state: StateAnimationIndex{index: 0},
         sprite: SpriteSheetBundle {
-            texture_atlas: atlas_handle.0.clone(),
+            texture_atlas: atlas_handle.clone(),
             sprite: TextureAtlasSprite::new(0),
             transform: Transform {
                 translation: grid_to_space(location, grid).extend(0.0),
@@ -77,6 +221,60 @@ pub fn spawn_wall(
         },
         grid_entity: GridEntity::new(location, None)
     })
-    .insert(Name::new("Wall"));
-    grid.tiles.insert(location, TileState::Wall);
-}
\ No newline at end of file
+    .insert(Name::new(format!("{} Wall", info.name)))
+    .id();
+    grid.set_object(location, Some(Object::Wall));
+    grid.set_owner(&[location], Some(entity));
+    Some(entity)
+}
+
+#[cfg(test)]
+mod wall_index_tests {
+    use super::{blob_index, reduce_blob_mask, wall_index, GridLayout, WallTypeInfo, WallTypes, BLOB_MASKS};
+    use bevy::prelude::IVec2;
+
+    #[test]
+    fn neighbour_bits() {
+        let mut walls = vec![IVec2::X, IVec2::NEG_Y];
+        assert_eq!(wall_index(GridLayout::Square, IVec2::ZERO, |cell| walls.contains(&cell)), blob_index(1 | 64));
+        walls.push(IVec2::new(1, -1));
+        assert_eq!(wall_index(GridLayout::Square, IVec2::ZERO, |cell| walls.contains(&cell)), blob_index(1 | 64 | 128));
+
+        let walls = [IVec2::new(-1, 1), IVec2::new(1, -1)];
+        assert_eq!(wall_index(GridLayout::Hex, IVec2::ZERO, |cell| walls.contains(&cell)), 4 | 32);
+    }
+
+    #[test]
+    fn connections() {
+        let wall = |name: &str, connects_across| WallTypeInfo {connects_across, name: name.to_string(), ..WallTypes::default().0[0].clone()};
+        let types = WallTypes(vec![wall("Stone", false), wall("Brick", true), wall("Steel", true)]);
+        assert!(types.connects(0, 0) && types.connects(1, 2) && types.connects(2, 1));
+        assert!(!types.connects(0, 1) && !types.connects(2, 0));
+        assert_eq!(types.by_name("Steel"), Some(2));
+        assert_eq!(types.default_name(), "Stone");
+    }
+
+    #[test]
+    fn blob_table() {
+        assert!(BLOB_MASKS.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(BLOB_MASKS.iter().all(|mask| reduce_blob_mask(*mask) == *mask));
+        let mut used = [false; 47];
+        for mask in 0..=255 {
+            used[blob_index(mask)] = true;
+        }
+        assert!(used.iter().all(|used| *used));
+    }
+
+    #[test]
+    fn blob_corners() {
+        assert_eq!(blob_index(0), 0);
+        assert_eq!(blob_index(255), 46);
+        // corners on their own, or next to only one side, look like nothing is there
+        assert_eq!(blob_index(2 | 8 | 32 | 128), blob_index(0));
+        assert_eq!(blob_index(1 | 2), blob_index(1));
+        // an inner corner only shows once both sides are there
+        assert_ne!(blob_index(1 | 2 | 4), blob_index(1 | 4));
+        assert_eq!(blob_index(1 | 4), 3);
+        assert_eq!(blob_index(1 | 2 | 4), 4);
+    }
+}
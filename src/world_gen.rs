@@ -0,0 +1,424 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::{collections::VecDeque, fs};
+
+use crate::{
+    asset_loading::{BuildingAtlasHandle, BuildingBindings, WallAtlasHandles},
+    building::{spawn_building, Rotation},
+    connectivity::ConnectivityRegions,
+    grid::{grid_to_space, Floor, Grid, GridScale, Object, Tile, SQUARE_NEIGHBOURS},
+    mining::{MiningConfig, ResourceNode},
+    walls::{spawn_wall, WallTypes},
+    AppState
+};
+
+const WORLD_GEN_PATH: &str = "assets/world_gen.ron";
+
+const ORE_COLOUR: Color = Color::rgb(0.75, 0.45, 0.2);
+pub const DEPLETED_ORE_COLOUR: Color = Color::rgb(0.45, 0.38, 0.32);
+const ROCK_COLOUR: Color = Color::rgb(0.42, 0.4, 0.38);
+
+
+pub struct WorldGenPlugin;
+
+impl Plugin for WorldGenPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(OnEnter(AppState::Finished), generate_world);
+    }
+}
+
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WorldGenConfig {
+    pub seed: u64,
+    /// everything is generated within this many tiles of the robot spawn
+    pub radius: i32,
+    /// tiles around the spawn that are left empty
+    pub spawn_clearance: i32,
+    pub deposit_count: usize,
+    /// smallest and largest number of tiles in a deposit
+    pub deposit_size: (usize, usize),
+    /// chance of any tile being rock
+    pub rock_density: f32,
+    pub wall_segments: usize,
+    pub wall_length: (i32, i32),
+    /// names of the building bindings to place near the spawn
+    pub starter_buildings: Vec<String>,
+}
+
+impl Default for WorldGenConfig {
+    fn default() -> Self {
+        WorldGenConfig {
+            seed: 1234,
+            radius: 40,
+            spawn_clearance: 4,
+            deposit_count: 8,
+            deposit_size: (4, 12),
+            rock_density: 0.05,
+            wall_segments: 10,
+            wall_length: (3, 8),
+            starter_buildings: Vec::new(),
+        }
+    }
+}
+
+/// SplitMix64, small and gives the same numbers on every platform
+pub struct SplitMix(u64);
+
+impl SplitMix {
+    pub fn new(seed: u64) -> Self {
+        SplitMix(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// a number between `min` and `max` inclusive
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        let span = (max - min + 1).max(1) as u64;
+        min + (self.next_u64() % span) as i32
+    }
+
+    pub fn chance(&mut self, probability: f32) -> bool {
+        ((self.next_u64() >> 40) as f32 / (1u64 << 24) as f32) < probability
+    }
+}
+
+
+/// What the generator decided to put where
+#[derive(Debug, Default, PartialEq)]
+pub struct GeneratedWorld {
+    pub ore_deposits: Vec<IVec2>,
+    pub rocks: Vec<IVec2>,
+    pub walls: Vec<IVec2>,
+    pub buildings: Vec<(String, IVec2)>,
+}
+
+/// Generates a world around a robot spawn at the origin, the same one every time for a given seed and map.
+/// Nothing is put past the edges of the map, given as its bottom left and top right tiles.
+///
+/// Once everything is placed, any deposit or starter building that can't be walked to from the spawn
+/// gets a path cleared to it through the fewest rocks and walls.
+pub fn generate(config: &WorldGenConfig, bindings: &BuildingBindings, bounds: Option<(IVec2, IVec2)>) -> GeneratedWorld {
+    let mut rng = SplitMix::new(config.seed);
+    let mut grid = Grid::new(Vec2::ZERO, 1.0);
+    if let Some((min, max)) = bounds {grid = grid.with_bounds(min, max);}
+    let mut world = GeneratedWorld::default();
+    let radius = config.radius;
+    // the square within `radius` of the spawn, cut down to the map
+    let (min, max) = bounds.map_or((IVec2::splat(-radius), IVec2::splat(radius)), |(min, max)| {
+        (min.max(IVec2::splat(-radius)), max.min(IVec2::splat(radius)))
+    });
+    let in_bounds = |cell: IVec2| cell.cmpge(min).all() && cell.cmple(max).all();
+    let near_spawn = |cell: IVec2, margin: i32| cell.abs().max_element() <= config.spawn_clearance + margin;
+
+    let mut deposit_starts = Vec::new();
+    for _ in 0..config.deposit_count {
+        let Some(centre) = (0..20)
+            .map(|_| IVec2::new(rng.range(min.x, max.x), rng.range(min.y, max.y)))
+            .find(|cell| !near_spawn(*cell, 2) && grid[*cell] == Tile::default()) else {continue};
+
+        let size = rng.range(config.deposit_size.0 as i32, config.deposit_size.1 as i32) as usize;
+        let mut cells = vec![centre];
+        grid.set_floor(centre, Floor::OreDeposit);
+        for _ in 0..size * 10 {
+            if cells.len() >= size {break;}
+            let from = cells[rng.range(0, cells.len() as i32 - 1) as usize];
+            let next = from + SQUARE_NEIGHBOURS[rng.range(0, 3) as usize];
+            if in_bounds(next) && !near_spawn(next, 0) && grid[next] == Tile::default() {
+                grid.set_floor(next, Floor::OreDeposit);
+                cells.push(next);
+            }
+        }
+        deposit_starts.push(centre);
+        world.ore_deposits.extend(cells);
+    }
+
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let cell = IVec2::new(x, y);
+            if rng.chance(config.rock_density) && !near_spawn(cell, 0) && grid[cell] == Tile::default() {
+                grid.set_object(cell, Some(Object::Rock));
+                world.rocks.push(cell);
+            }
+        }
+    }
+
+    for _ in 0..config.wall_segments {
+        let mut cell = IVec2::new(rng.range(min.x, max.x), rng.range(min.y, max.y));
+        let dir = SQUARE_NEIGHBOURS[rng.range(0, 3) as usize];
+        for _ in 0..rng.range(config.wall_length.0, config.wall_length.1) {
+            if in_bounds(cell) && !near_spawn(cell, 0) && grid[cell] == Tile::default() {
+                grid.set_object(cell, Some(Object::Wall));
+                world.walls.push(cell);
+            }
+            cell += dir;
+        }
+    }
+
+    let mut targets = deposit_starts;
+    let ring = config.spawn_clearance + 6;
+    for name in config.starter_buildings.iter() {
+        let Some(spawn_info) = bindings.by_name(name) else {
+            warn!("Unknown starter building {}", name);
+            continue;
+        };
+        let fits = |location: IVec2, grid: &Grid| {
+            let footprint = (0..=spawn_info.max_offset.x).flat_map(|x| (0..=spawn_info.max_offset.y).map(move |y| location + IVec2::new(x, y)));
+            footprint.clone().all(|cell| in_bounds(cell) && !near_spawn(cell, 0) && grid[cell] == Tile::default())
+                && spawn_info.interaction_points.iter().all(|offset| in_bounds(location + *offset) && grid[location + *offset] == Tile::default())
+        };
+        let Some(location) = (0..100)
+            .map(|_| IVec2::new(rng.range(-ring, ring), rng.range(-ring, ring)))
+            .find(|location| fits(*location, &grid)) else {continue};
+
+        for x in 0..=spawn_info.max_offset.x {
+            for y in 0..=spawn_info.max_offset.y {
+                grid.set_object(location + IVec2::new(x, y), Some(Object::Building));
+            }
+        }
+        for offset in spawn_info.interaction_points.iter() {
+            grid.add_interaction_point(location + *offset);
+        }
+        targets.extend(spawn_info.interaction_points.first().map(|offset| location + *offset));
+        world.buildings.push((name.clone(), location));
+    }
+
+    let mut regions = ConnectivityRegions::default();
+    regions.rebuild(&grid);
+    for target in targets {
+        if regions.is_reachable(IVec2::ZERO, target) {continue;}
+        for cell in clear_path(&grid, target, radius + 1) {
+            if !matches!(grid[cell].object, Some(Object::Rock | Object::Wall)) {continue;}
+            grid.set_object(cell, None);
+            world.rocks.retain(|rock| *rock != cell);
+            world.walls.retain(|wall| *wall != cell);
+            regions.update_cell(cell, &grid);
+        }
+    }
+
+    world
+}
+
+/// The route from the spawn to `target` crossing the fewest rocks and walls, never going through buildings
+/// or off the map
+fn clear_path(grid: &Grid, target: IVec2, bound: i32) -> Vec<IVec2> {
+    let mut cost = bevy::utils::HashMap::new();
+    let mut came_from = bevy::utils::HashMap::new();
+    let mut queue = VecDeque::from([IVec2::ZERO]);
+    cost.insert(IVec2::ZERO, 0);
+
+    // 0-1 breadth first search, open tiles go on the front of the queue and obstacles on the back
+    while let Some(current) = queue.pop_front() {
+        if current == target {break;}
+        for dir in SQUARE_NEIGHBOURS {
+            let next = current + dir;
+            if next.abs().max_element() > bound || !grid.in_bounds(next) || grid[next].object == Some(Object::Building) {continue;}
+            let step = if grid.is_walkable(next) {0} else {1};
+            let next_cost = cost[&current] + step;
+            if next_cost < *cost.get(&next).unwrap_or(&u32::MAX) {
+                cost.insert(next, next_cost);
+                came_from.insert(next, current);
+                if step == 0 {queue.push_front(next)} else {queue.push_back(next)}
+            }
+        }
+    }
+
+    let mut path = Vec::new();
+    let mut current = target;
+    while let Some(previous) = came_from.get(&current) {
+        path.push(current);
+        current = *previous;
+    }
+    path
+}
+
+
+/// Ground that isn't a grid entity, such as ore and rock
+#[derive(Component)]
+pub struct Terrain {
+    pub pos: IVec2
+}
+
+/// ore in the floor of an empty tile, which runs out after `remaining` is mined if that is set
+pub fn spawn_ore(
+    commands: &mut Commands,
+    grid: &mut Grid,
+    pos: IVec2,
+    remaining: Option<u32>
+) -> Option<Entity> {
+    if !grid.is_free(pos) || grid[pos].floor != Floor::Ground {return None}
+    let node = ResourceNode {remaining};
+    let (floor, colour) = if node.is_depleted() {(Floor::DepletedOre, DEPLETED_ORE_COLOUR)} else {(Floor::OreDeposit, ORE_COLOUR)};
+    grid.set_floor(pos, floor);
+
+    let entity = spawn_terrain_sprite(commands, grid, pos, colour, "Ore");
+    commands.entity(entity).insert(node);
+    Some(entity)
+}
+
+pub fn spawn_rock(
+    commands: &mut Commands,
+    grid: &mut Grid,
+    pos: IVec2
+) -> Option<Entity> {
+    if !grid.is_free(pos) {return None}
+    grid.set_object(pos, Some(Object::Rock));
+    Some(spawn_terrain_sprite(commands, grid, pos, ROCK_COLOUR, "Rock"))
+}
+
+fn spawn_terrain_sprite(commands: &mut Commands, grid: &Grid, pos: IVec2, colour: Color, name: &str) -> Entity {
+    commands.spawn(SpriteBundle {
+        sprite: Sprite {
+            color: colour,
+            custom_size: Some(Vec2::splat(grid.tile_size)),
+            ..Default::default()
+        },
+        transform: Transform::from_translation(grid_to_space(pos, grid).extend(-0.1)),
+        ..Default::default()
+    })
+    .insert((Terrain {pos}, Name::new(name.to_string())))
+    .id()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn generate_world(
+    mut commands: Commands,
+    mut grid: ResMut<Grid>,
+    grid_scale: Res<GridScale>,
+    wall_types: Res<WallTypes>,
+    wall_atlases: Res<WallAtlasHandles>,
+    building_atlas: Res<BuildingAtlasHandle>,
+    bindings: Res<BuildingBindings>,
+    mining: Res<MiningConfig>
+) {
+    let config = fs::read_to_string(WORLD_GEN_PATH).map_err(|e| e.to_string())
+        .and_then(|text| ron::from_str::<WorldGenConfig>(&text).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            warn!("Failed to load {}, using the default world: {}", WORLD_GEN_PATH, e);
+            WorldGenConfig::default()
+        });
+    let world = generate(&config, &bindings, grid.bounds());
+
+    for cell in world.ore_deposits {
+        spawn_ore(&mut commands, &mut grid, cell, mining.vein_yield);
+    }
+    for cell in world.rocks {
+        spawn_rock(&mut commands, &mut grid, cell);
+    }
+    for cell in world.walls {
+        spawn_wall(&mut commands, &mut grid, &grid_scale, cell, 0, &wall_types, &wall_atlases);
+    }
+    for (name, location) in world.buildings {
+        let Some(spawn_info) = bindings.by_name(&name) else {continue};
+        spawn_building(&mut commands, &mut grid, &grid_scale, location, Rotation::R0, spawn_info, &building_atlas);
+    }
+    info!("Generated world from seed {}", config.seed);
+}
+
+
+#[cfg(test)]
+mod world_gen_tests {
+    use super::{generate, GeneratedWorld, WorldGenConfig};
+    use crate::{
+        asset_loading::{BuildingBindings, BuildingSpawnInfo},
+        connectivity::ConnectivityRegions,
+        grid::{Floor, Grid, Object}
+    };
+    use bevy::{prelude::*, utils::{HashMap, HashSet}};
+
+    fn bindings() -> BuildingBindings {
+        BuildingBindings(HashMap::from([(0, BuildingSpawnInfo {
+            sprite_index: 0,
+            name: "Smelter".to_string(),
+            max_offset: IVec2::new(1, 1),
+            interaction_points: vec![IVec2::new(-1, 0), IVec2::new(2, 1)],
+            requires_node: false
+        })]))
+    }
+
+    fn config(seed: u64) -> WorldGenConfig {
+        WorldGenConfig {
+            seed,
+            // dense enough that some deposits start off walled in
+            rock_density: 0.35,
+            starter_buildings: vec!["Smelter".to_string(), "Smelter".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn to_grid(world: &GeneratedWorld) -> Grid {
+        to_bounded_grid(world, Grid::new(Vec2::ZERO, 1.0))
+    }
+
+    fn to_bounded_grid(world: &GeneratedWorld, mut grid: Grid) -> Grid {
+        for cell in world.ore_deposits.iter() {grid.set_floor(*cell, Floor::OreDeposit);}
+        for cell in world.rocks.iter() {grid.set_object(*cell, Some(Object::Rock));}
+        for cell in world.walls.iter() {grid.set_object(*cell, Some(Object::Wall));}
+        for (_, location) in world.buildings.iter() {
+            for offset in [IVec2::ZERO, IVec2::X, IVec2::Y, IVec2::ONE] {grid.set_object(*location + offset, Some(Object::Building));}
+        }
+        grid
+    }
+
+    #[test]
+    fn deterministic() {
+        let first = generate(&config(7), &bindings(), None);
+        assert_eq!(first, generate(&config(7), &bindings(), None));
+        assert_ne!(first, generate(&config(8), &bindings(), None));
+        assert!(!first.ore_deposits.is_empty() && !first.rocks.is_empty() && !first.walls.is_empty());
+        assert_eq!(first.buildings.len(), 2);
+    }
+
+    #[test]
+    fn nothing_overlaps() {
+        let world = generate(&config(99), &bindings(), None);
+        let mut seen = HashSet::new();
+        for cell in world.ore_deposits.iter().chain(world.rocks.iter()).chain(world.walls.iter()) {
+            assert!(seen.insert(*cell), "{} used twice", cell);
+            assert!(cell.abs().max_element() > 4, "{} is in the spawn clearing", cell);
+        }
+    }
+
+    #[test]
+    fn everything_reachable() {
+        for seed in 0..10 {
+            let world = generate(&config(seed), &bindings(), None);
+            let grid = to_grid(&world);
+            let mut regions = ConnectivityRegions::default();
+            regions.rebuild(&grid);
+
+            for cell in world.ore_deposits.iter() {
+                assert!(regions.is_reachable(IVec2::ZERO, *cell), "seed {}: deposit at {} is cut off", seed, cell);
+            }
+            for (_, location) in world.buildings.iter() {
+                assert!(regions.is_reachable(IVec2::ZERO, *location - IVec2::X), "seed {}: building at {} is cut off", seed, location);
+            }
+        }
+    }
+
+    #[test]
+    fn stays_on_the_map() {
+        // a map smaller than the generated area, with the spawn near its bottom edge
+        let (min, max) = (IVec2::new(-12, -7), IVec2::new(15, 20));
+        for seed in 0..10 {
+            let world = generate(&config(seed), &bindings(), Some((min, max)));
+            let inside = |cell: &IVec2| cell.cmpge(min).all() && cell.cmple(max).all();
+            assert!(world.ore_deposits.iter().chain(world.rocks.iter()).chain(world.walls.iter()).all(inside), "seed {}", seed);
+            assert!(world.buildings.iter().all(|(_, location)| inside(location) && inside(&(*location + IVec2::ONE))), "seed {}", seed);
+
+            let grid = to_bounded_grid(&world, Grid::new(Vec2::ZERO, 1.0).with_bounds(min, max));
+            let mut regions = ConnectivityRegions::default();
+            regions.rebuild(&grid);
+            for cell in world.ore_deposits.iter() {
+                assert!(regions.is_reachable(IVec2::ZERO, *cell), "seed {}: deposit at {} is cut off", seed, cell);
+            }
+        }
+    }
+}